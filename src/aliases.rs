@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+use crate::config::{AliasConfig, Config};
+
+// Short names for common fields of each Query API endpoint.
+const BUILT_IN_ALIASES: &[(&str, &[(&str, &str)])] = &[
+    ("transactions", &[("amount", "TotalAmount"), ("net", "NetAmount"), ("fee", "FeeAmount"), ("date", "TransactionDate"), ("id", "PaymentTransId"), ("batch", "BatchNumber")]),
+    ("settlements", &[("amount", "NetAmount"), ("date", "SettlementDate"), ("id", "PaymentTransId"), ("batch", "BatchNumber")]),
+    ("batches", &[("amount", "BatchAmount"), ("date", "BatchDate"), ("id", "IdBatch"), ("batch", "BatchNumber")]),
+    ("chargebacks", &[("amount", "NetAmount"), ("date", "ChargebackDate"), ("id", "IdChargeback")]),
+    ("customers", &[("id", "customerId"), ("number", "customerNumber"), ("first", "Firstname"), ("last", "Lastname"), ("email", "Email"), ("date", "Created")]),
+    ("payouts", &[("amount", "TotalAmount"), ("date", "PaymentDate"), ("id", "IdOut")]),
+    ("subscriptions", &[("amount", "TotalAmount"), ("date", "StartDate"), ("next", "NextDate"), ("id", "IdSub")]),
+];
+
+pub struct Aliases {
+    fields: HashMap<String, String>,
+}
+
+impl Aliases {
+    // The built-in aliases of `endpoint`, then the `aliases` section of payquery.yml, where
+    // aliases for a single endpoint take precedence over those for every endpoint.
+    pub fn load(endpoint: Option<&str>, config: Option<&Config>) -> Self {
+        let is_endpoint = |name: &str| endpoint.is_some_and(|endpoint| endpoint.eq_ignore_ascii_case(name));
+        let mut fields = HashMap::new();
+
+        for (_, aliases) in BUILT_IN_ALIASES.iter().filter(|(name, _)| is_endpoint(name)) {
+            fields.extend(aliases.iter().map(|(alias, field)| (alias.to_string(), field.to_string())));
+        }
+        let user_aliases = config.map(|config| &config.aliases);
+        for (alias, config) in user_aliases.into_iter().flatten() {
+            if let AliasConfig::Field(field) = config {
+                fields.insert(alias.to_lowercase(), field.clone());
+            }
+        }
+        for (name, config) in user_aliases.into_iter().flatten() {
+            if let AliasConfig::Endpoint(aliases) = config
+                && is_endpoint(name) {
+                fields.extend(aliases.iter().map(|(alias, field)| (alias.to_lowercase(), field.clone())));
+            }
+        }
+
+        Aliases { fields }
+    }
+
+    // Replaces an alias used as the whole path or as its first segment (`customer.email`).
+    pub fn resolve(&self, path: &str) -> String {
+        if let Some(field) = self.fields.get(&path.to_lowercase()) {
+            return field.clone();
+        }
+        match path.split_once('.') {
+            Some((first, rest)) => match self.fields.get(&first.to_lowercase()) {
+                Some(field) => format!("{}.{}", field, rest),
+                None => path.to_string(),
+            },
+            None => path.to_string(),
+        }
+    }
+
+    // Resolves the field in filter keys such as `amount(gt)`.
+    pub fn resolve_filters(&self, filters: Vec<(String, String)>) -> Vec<(String, String)> {
+        filters.into_iter()
+            .map(|(key, value)| match key.strip_suffix(')').and_then(|key| key.split_once('(')) {
+                Some((field, condition)) => (format!("{}({})", self.resolve(field), condition), value),
+                None => (key, value),
+            })
+            .collect()
+    }
+}
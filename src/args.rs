@@ -1,85 +1,86 @@
-use std::collections::HashMap;
-use chrono::{Utc, Duration, Datelike, NaiveDate, Month};
-use std::str::FromStr;
-
-pub fn get_keyword_map() -> HashMap<&'static str, String> {
-    let now = Utc::now();
-    let today = now.date_naive().and_hms_opt(0, 0, 0).unwrap(); 
-    let yesterday = today - Duration::try_days(1).unwrap(); 
-    let tomorrow = today + Duration::try_days(1).unwrap(); 
-
-    let mut map = HashMap::new();
-    map.insert("now", now.format("%Y-%m-%dT%H:%M:%S%.3f").to_string());
-    map.insert("today", today.format("%Y-%m-%dT%H:%M:%S%.3f").to_string());
-    map.insert("yesterday", yesterday.format("%Y-%m-%dT%H:%M:%S%.3f").to_string());
-    map.insert("tomorrow", tomorrow.format("%Y-%m-%dT%H:%M:%S%.3f").to_string());
-
-    // Add time frame keywords
-    let start_of_week = today - Duration::days(today.weekday().num_days_from_monday() as i64);
-    let start_of_next_week = start_of_week + Duration::weeks(1);
-    let start_of_last_week = start_of_week - Duration::weeks(1);
-
-    let start_of_month = today.with_day(1).unwrap();
-    let start_of_next_month = start_of_month + Duration::days(31);
-    let start_of_last_month = start_of_month - Duration::days(1);
-
-    let start_of_year = today.with_ordinal(1).unwrap();
-    let start_of_next_year = start_of_year + Duration::days(365);
-    let start_of_last_year = start_of_year - Duration::days(1);
-
-    map.insert("this week", start_of_week.format("%Y-%m-%dT%H:%M:%S%.3f").to_string());
-    map.insert("next week", start_of_next_week.format("%Y-%m-%dT%H:%M:%S%.3f").to_string());
-    map.insert("last week", start_of_last_week.format("%Y-%m-%dT%H:%M:%S%.3f").to_string());
-
-    map.insert("this month", start_of_month.format("%Y-%m-%dT%H:%M:%S%.3f").to_string());
-    map.insert("next month", start_of_next_month.format("%Y-%m-%dT%H:%M:%S%.3f").to_string());
-    map.insert("last month", start_of_last_month.format("%Y-%m-%dT%H:%M:%S%.3f").to_string());
-
-    map.insert("this year", start_of_year.format("%Y-%m-%dT%H:%M:%S%.3f").to_string());
-    map.insert("next year", start_of_next_year.format("%Y-%m-%dT%H:%M:%S%.3f").to_string());
-    map.insert("last year", start_of_last_year.format("%Y-%m-%dT%H:%M:%S%.3f").to_string());
-
-    map
-}
-
-pub fn replace_keywords(arg: &str, keyword_map: &HashMap<&str, String>) -> String {
-    let mut replaced_arg = arg.to_string();
-    let mut sorted_keywords: Vec<_> = keyword_map.keys().collect();
-    sorted_keywords.sort_by_key(|k| std::cmp::Reverse(k.len()));
-    for keyword in sorted_keywords {
-        if let Some(replacement) = keyword_map.get(keyword) {
-            replaced_arg = replaced_arg.replace(keyword, replacement);
-        }
+use std::fs;
+use std::io::{self, Read};
+use chrono_tz::Tz;
+use crate::dates::{parse_date, parse_period};
+use crate::path::{select, validate_path};
+
+// Fields tried in order when no --key is given. The first one present in a record identifies it.
+const ID_FIELDS: [&str; 9] = ["IdTrans", "PaymentTransId", "IdBatch", "IdOut", "IdChargeback", "customerId", "IdSub", "Id", "id"];
+
+// Options followed by a value.
+const VALUE_OPTIONS: [&str; 7] = ["--key", "--from-file", "--tz", "--retries", "--rate-limit", "--out", "--hook"];
+
+// Marks the words that belong to a quoted filter value such as `"paid for lunch"`, so that
+// clause keywords inside it are not treated as clauses.
+pub fn quoted_words(args: &[String]) -> Vec<bool> {
+    let mut quote: Option<char> = None;
+    args.iter()
+        .map(|arg| {
+            let quoted = quote.is_some() || arg.contains(['"', '\'']);
+            // A quote opens at the start of a value (possibly inside a list) and closes at the
+            // next matching quote, so apostrophes in words like O'Brien are left alone.
+            let mut at_start = true;
+            for c in arg.chars() {
+                match quote {
+                    Some(open) if c == open => quote = None,
+                    None if at_start && matches!(c, '"' | '\'') => quote = Some(c),
+                    _ => {}
+                }
+                at_start = matches!(c, '(' | '[' | ',');
+            }
+            quoted
+        })
+        .collect()
+}
+
+// Skips quoted words and the word right after an operator, which is a filter value as in
+// `Description eq into`.
+pub fn clause_position(args: &[String], keyword: &str) -> Option<usize> {
+    let quoted = quoted_words(args);
+    (0..args.len()).find(|&i| {
+        !quoted[i] && args[i] == keyword && (i == 0 || get_condition(&args[i - 1]).is_none())
+    })
+}
+
+// Subcommands such as `watch` and `diff` may follow options (`--quiet watch 30s ...`), so the
+// subcommand is the first word that is neither an option nor the value of one.
+pub fn subcommand_position(args: &[String]) -> Option<usize> {
+    let mut i = 0;
+    while i < args.len() && args[i].starts_with("--") {
+        i += if VALUE_OPTIONS.contains(&args[i].as_str()) { 2 } else { 1 };
     }
-    replaced_arg
+    (i < args.len()).then_some(i)
 }
 
-pub fn extract_only_clause(args: &[String]) -> Option<usize> {
-    let only_pos = args.iter().position(|x| x == "only");
-    only_pos.and_then(|pos| args.get(pos + 1).and_then(|value| value.parse::<usize>().ok()))
+pub fn extract_option(args: &[String], option: &str) -> Result<(Vec<String>, Option<String>), String> {
+    match args.iter().position(|x| x == option) {
+        Some(pos) => {
+            let value = args.get(pos + 1).ok_or_else(|| format!("Missing value after '{}'", option))?.clone();
+            let remaining = args[..pos].iter().chain(&args[pos + 2..]).cloned().collect();
+            Ok((remaining, Some(value)))
+        }
+        None => Ok((args.to_vec(), None)),
+    }
 }
 
 pub fn extract_for_clause(args: &[String]) -> String {
-    let for_pos = args.iter().position(|x| x == "for");
+    let for_pos = clause_position(args, "for");
     for_pos.and_then(|pos| args.get(pos + 1).cloned()).unwrap_or_else(|| "default".to_string())
 }
 
 pub fn split_args(args: &[String]) -> (Vec<String>, Vec<String>, Option<(String, String)>) {
-    let only_pos = args.iter().position(|x| x == "only").map(|pos| pos + 2);
-    let for_pos = args.iter().position(|x| x == "for");
-    let where_pos = args.iter().position(|x| x == "where");
-    let by_pos = args.iter().position(|x| x == "by");
+    let for_pos = clause_position(args, "for");
+    let where_pos = clause_position(args, "where");
+    let by_pos = clause_position(args, "by");
 
     let first_clause_pos = [for_pos, where_pos, by_pos]
         .iter()
         .filter_map(|&pos| pos)
         .min();
     
-    let route_parts = match (only_pos, first_clause_pos) {
-        (Some(only_pos), Some(first_pos)) => args[only_pos..first_pos].to_vec(),
-        (Some(only_pos), None) => args[only_pos..].to_vec(),
-        (None, Some(first_pos)) => args[..first_pos].to_vec(),
-        (None, None) => args.to_vec(),
+    let route_parts = match first_clause_pos {
+        Some(first_pos) => args[..first_pos].to_vec(),
+        None => args.to_vec(),
     };
 
     let filter_args = match (where_pos, by_pos) {
@@ -102,59 +103,167 @@ pub fn build_url(base: &str, route_parts: &[String]) -> String {
     format!("{}/api/Query/{}/", base, path)
 }
 
-pub fn replace_keywords_in_args(args: &[String]) -> String {
-    let args_string = args.join(" ");
-    let keyword_map = get_keyword_map();
-    let replaced_args = replace_keywords(&args_string, &keyword_map);
-    replace_human_readable_dates(&replaced_args)
-}
-
-fn replace_human_readable_dates(input: &str) -> String {
-    let mut result = input.to_string();
-    let re = regex::Regex::new(r"(?i)\b([A-Za-z]+) (\d{1,2})(?: (\d{4}))?(?: @ (\d{1,2}):(\d{2}))?\b").unwrap();
-    let now = Utc::now();
-    let current_year = now.year();
-
-    for cap in re.captures_iter(input) {
-        let month_str = &cap[1];
-        let day: u32 = cap[2].parse().unwrap();
-        let year: i32 = cap.get(3).map_or(current_year, |m| m.as_str().parse().unwrap());
-
-        if let Ok(month) = Month::from_str(month_str) {
-            if let Some(date) = NaiveDate::from_ymd_opt(year, month.number_from_month(), day) {
-                let hour: u32 = cap.get(4).map_or(0, |m| m.as_str().parse().unwrap());
-                let minute: u32 = cap.get(5).map_or(0, |m| m.as_str().parse().unwrap());
-                let datetime = date.and_hms_opt(hour, minute, 0).unwrap();
-                let utc_datetime = datetime.format("%Y-%m-%dT%H:%M:%S%.3f").to_string();
-                result = result.replace(&cap[0], &utc_datetime);
+// Splits on commas, except inside quoted values and `(...)` or `[...]` lists.
+fn split_clauses(input: &str) -> Vec<String> {
+    let mut clauses = vec![String::new()];
+    let mut quote: Option<char> = None;
+    let mut depth = 0usize;
+    let mut previous = ' ';
+    for c in input.chars() {
+        let at_start = previous.is_whitespace() || matches!(previous, '(' | '[' | ',');
+        previous = c;
+        match (c, quote) {
+            ('"' | '\'', None) if at_start => quote = Some(c),
+            (c, Some(open)) if c == open => quote = None,
+            ('(' | '[', None) => depth += 1,
+            (')' | ']', None) => depth = depth.saturating_sub(1),
+            (',', None) if depth == 0 => {
+                clauses.push(String::new());
+                continue;
             }
+            _ => {}
         }
+        clauses.last_mut().unwrap().push(c);
     }
+    clauses.into_iter().map(|clause| clause.trim().to_string()).filter(|clause| !clause.is_empty()).collect()
+}
 
-    result
+// Date expressions in a value become API timestamps. A quoted value is taken literally,
+// so `name eq "Today"` or `Description ct "March 3"` are not read as dates.
+fn resolve_value(value: &str, tz: Tz) -> Result<String, String> {
+    let quoted = ['"', '\''].iter().find_map(|&quote| value.strip_prefix(quote)?.strip_suffix(quote));
+    if let Some(literal) = quoted {
+        return Ok(literal.to_string());
+    }
+    if let Some(timestamp) = parse_date(value, tz) {
+        return Ok(timestamp);
+    }
+    if value.contains(char::is_whitespace) {
+        return Err(format!("Invalid value '{}' (quote values that contain spaces)", value));
+    }
+    Ok(value.to_string())
 }
 
-pub fn parse_filters(args: &[String]) -> Result<Vec<(String, String)>, String> {
-    let replaced_args_string = replace_keywords_in_args(args);
+// Values for `in` and `not_in`: a `(1, 2, 5)` or `[card, ach]` list, `@FILE` with one value per
+// line (`@-` reads stdin), or values already joined with '|', which is how the API encodes lists.
+fn resolve_list(value: &str, tz: Tz) -> Result<String, String> {
+    let items = if let Some(path) = value.strip_prefix('@') {
+        let text = if path == "-" {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text).map_err(|e| format!("Error reading stdin: {}", e))?;
+            text
+        } else {
+            fs::read_to_string(path).map_err(|e| format!("Error reading '{}': {}", path, e))?
+        };
+        text.lines().flat_map(split_clauses).collect()
+    } else if let Some(list) = value.strip_prefix('(').and_then(|list| list.strip_suffix(')'))
+        .or_else(|| value.strip_prefix('[').and_then(|list| list.strip_suffix(']'))) {
+        split_clauses(list)
+    } else {
+        value.split('|').map(|item| item.trim().to_string()).filter(|item| !item.is_empty()).collect()
+    };
 
-    replaced_args_string.split(',')
-        .map(str::trim)
-        .filter(|s| !s.is_empty())
-        .map(parse_filter_clause)
-        .collect()
+    if items.is_empty() {
+        return Err(format!("Empty list '{}'", value));
+    }
+    let items = items.iter().map(|item| resolve_value(item, tz)).collect::<Result<Vec<_>, _>>()?;
+    if let Some(item) = items.iter().find(|item| item.contains('|')) {
+        return Err(format!("Invalid list item '{}' ('|' separates list values)", item));
+    }
+    Ok(items.join("|"))
+}
+
+pub fn parse_filters(args: &[String], tz: Tz) -> Result<Vec<(String, String)>, String> {
+    let mut filters = Vec::new();
+    for clause in split_clauses(&args.join(" ")) {
+        filters.extend(parse_filter_clause(&clause, tz)?);
+    }
+    for (key, _) in &filters {
+        validate_path(key.split('(').next().unwrap_or_default())?;
+    }
+    Ok(filters)
 }
 
-pub fn parse_filter_clause(clause: &str) -> Result<(String, String), String> {
+// `FIELD between A and B` and `FIELD during PERIOD` become a `ge`/`lt` pair, so the end is excluded.
+// Null checks have no API operator and are evaluated locally (see `records::is_local_filter`).
+pub fn parse_filter_clause(clause: &str, tz: Tz) -> Result<Vec<(String, String)>, String> {
     let parts: Vec<&str> = clause.split_whitespace().collect();
     match parts.as_slice() {
-        [field, condition, value] => get_condition(condition)
-            .map(|cond| (format!("{}({})", field, cond), value.to_string()))
-            .ok_or_else(|| format!("Invalid condition '{}' for field '{}'", condition, field)),
-        [field, value] => Ok((format!("{}(eq)", field), value.to_string())),
+        ["not", rest @ ..] if !rest.is_empty() => negate(parse_filter_clause(&rest.join(" "), tz)?),
+        [field, "is", "null"] => Ok(vec![(format!("{}(isnull)", field), String::new())]),
+        [field, "is", "not", "null"] => Ok(vec![(format!("{}(notnull)", field), String::new())]),
+        [field, "exists"] => Ok(vec![(format!("{}(exists)", field), String::new())]),
+        [field, "between", range @ ..] => {
+            let (start, end) = range.iter().position(|word| *word == "and")
+                .map(|pos| (range[..pos].join(" "), range[pos + 1..].join(" ")))
+                .filter(|(start, end)| !start.is_empty() && !end.is_empty())
+                .ok_or_else(|| format!("Invalid range for field '{}' (use '{} between A and B')", field, field))?;
+            Ok(vec![
+                (format!("{}(ge)", field), resolve_value(&start, tz)?),
+                (format!("{}(lt)", field), resolve_value(&end, tz)?),
+            ])
+        }
+        [field, "during", period @ ..] => {
+            let period = period.join(" ");
+            let (start, end) = parse_period(&period, tz)
+                .ok_or_else(|| format!("Unknown period '{}' for field '{}'", period, field))?;
+            Ok(vec![(format!("{}(ge)", field), start), (format!("{}(lt)", field), end)])
+        }
+        [field, condition, value @ ..] if !value.is_empty() && get_condition(condition).is_some() => {
+            let condition = get_condition(condition).unwrap();
+            let value = match condition {
+                "in" | "nin" => resolve_list(&value.join(" "), tz)?,
+                _ => resolve_value(&value.join(" "), tz)?,
+            };
+            Ok(vec![(format!("{}({})", field, condition), value)])
+        }
+        [field, value] => Ok(vec![(format!("{}(eq)", field), resolve_value(value, tz)?)]),
+        [field, value @ ..] if parse_date(&value.join(" "), tz).is_some() => {
+            Ok(vec![(format!("{}(eq)", field), resolve_value(&value.join(" "), tz)?)])
+        }
+        [field, condition, _, ..] => Err(format!("Invalid condition '{}' for field '{}'", condition, field)),
         _ => Err(format!("Invalid filter clause: '{}'", clause)),
     }
 }
 
+// Inverts a parsed clause. A range becomes the local `out` condition (below the start or at or
+// after the end), since the API joins filters with AND.
+fn negate(filters: Vec<(String, String)>) -> Result<Vec<(String, String)>, String> {
+    let split = |key: &str| key.strip_suffix(')').and_then(|key| key.split_once('(')).map(|(field, condition)| (field.to_string(), condition.to_string()));
+    match filters.as_slice() {
+        [(key, value)] => {
+            let (field, condition) = split(key).ok_or_else(|| format!("Invalid filter '{}'", key))?;
+            let negated = match condition.as_str() {
+                "out" => {
+                    let (start, end) = value.split_once('|').ok_or_else(|| format!("Invalid range '{}'", value))?;
+                    return Ok(vec![(format!("{}(ge)", field), start.to_string()), (format!("{}(lt)", field), end.to_string())]);
+                }
+                "eq" => "ne",
+                "ne" => "eq",
+                "gt" => "le",
+                "le" => "gt",
+                "ge" => "lt",
+                "lt" => "ge",
+                "ct" => "nct",
+                "nct" => "ct",
+                "in" => "nin",
+                "nin" => "in",
+                "isnull" => "notnull",
+                "notnull" => "isnull",
+                "exists" => "nexists",
+                "nexists" => "exists",
+                other => return Err(format!("Cannot negate condition '{}'", other)),
+            };
+            Ok(vec![(format!("{}({})", field, negated), value.clone())])
+        }
+        [(start_key, start), (_, end)] => {
+            let (field, _) = split(start_key).ok_or_else(|| format!("Invalid filter '{}'", start_key))?;
+            Ok(vec![(format!("{}(out)", field), format!("{}|{}", start, end))])
+        }
+        _ => Err("Cannot negate this filter".to_string()),
+    }
+}
+
 pub fn get_condition(arg: &str) -> Option<&'static str> {
     match arg {
         "=" | "eq" => Some("eq"),
@@ -171,6 +280,18 @@ pub fn get_condition(arg: &str) -> Option<&'static str> {
     }
 }
             
+// The first value a field path selects (see `path::select`); keys match case-insensitively,
+// so `by lastname` finds `Lastname`.
 pub fn get_nested_value<'a>(value: &'a serde_json::Value, key: &str) -> Option<&'a serde_json::Value> {
-    key.split('.').fold(Some(value), |acc, k| acc.and_then(|v| v.get(k)))
+    select(value, key).into_iter().next()
+}
+
+pub fn record_key(record: &serde_json::Value, key: Option<&str>) -> String {
+    let id = match key {
+        Some(key) => get_nested_value(record, key),
+        None => ID_FIELDS.iter().find_map(|field| record.get(field)),
+    };
+    id.filter(|value| !value.is_null())
+        .map(|value| value.to_string())
+        .unwrap_or_else(|| record.to_string())
 }
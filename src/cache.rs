@@ -0,0 +1,99 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+pub const DEFAULT_TTL_SECS: u64 = 60;
+
+pub struct ResponseCache {
+    dir: PathBuf,
+    ttl: Duration,
+    refresh: bool,
+}
+
+impl ResponseCache {
+    // Responses hold cardholder and bank data, so caching is opt-in: it is enabled by --cache or
+    // a `cache_ttl` in the configuration, and disabled by --no-cache or a TTL of zero.
+    pub fn from_args(args: &[String], ttl_secs: Option<u64>) -> Option<Self> {
+        if ttl_secs.is_none() && !args.contains(&"--cache".to_string()) {
+            return None;
+        }
+        let ttl = Duration::from_secs(ttl_secs.unwrap_or(DEFAULT_TTL_SECS));
+        if args.contains(&"--no-cache".to_string()) || ttl.is_zero() {
+            return None;
+        }
+        Some(ResponseCache {
+            dir: Self::get_cache_dir()?,
+            ttl,
+            refresh: args.contains(&"--refresh".to_string()),
+        })
+    }
+
+    fn get_cache_dir() -> Option<PathBuf> {
+        dirs::cache_dir().map(|dir| dir.join("payquery"))
+    }
+
+    // The token is part of the key, so configurations that share an environment and entrypoint
+    // don't read each other's responses.
+    pub fn key(environment: &str, api_token: &str, url: &str, query_params: &[(String, String)]) -> String {
+        let mut hasher = DefaultHasher::new();
+        (environment, api_token, url, query_params).hash(&mut hasher);
+        format!("{:016x}", hasher.finish())
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    // Returns the cached body and its age, unless it is older than the TTL or --refresh was given.
+    pub fn read(&self, key: &str) -> Option<(String, Duration)> {
+        if self.refresh {
+            return None;
+        }
+        let path = self.entry_path(key);
+        let modified = fs::metadata(&path).and_then(|meta| meta.modified()).ok()?;
+        let age = SystemTime::now().duration_since(modified).unwrap_or_default();
+        if age > self.ttl {
+            return None;
+        }
+        fs::read_to_string(path).ok().map(|text| (text, age))
+    }
+
+    // The cache directory and its files are only readable by the current user.
+    pub fn write(&self, key: &str, text: &str) -> Result<(), String> {
+        fs::create_dir_all(&self.dir).map_err(|e| e.to_string())?;
+        #[cfg(unix)]
+        fs::set_permissions(&self.dir, fs::Permissions::from_mode(0o700)).map_err(|e| e.to_string())?;
+        let path = self.entry_path(key);
+        let tmp_path = path.with_extension("tmp");
+        // A leftover temporary file would keep its permissions, so it is replaced.
+        let _ = fs::remove_file(&tmp_path);
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        options.open(&tmp_path)
+            .and_then(|mut file| file.write_all(text.as_bytes()))
+            .map_err(|e| e.to_string())?;
+        fs::rename(&tmp_path, &path).map_err(|e| e.to_string())
+    }
+
+    pub fn clear() -> Result<usize, String> {
+        let Some(dir) = Self::get_cache_dir().filter(|dir| dir.exists()) else {
+            return Ok(0);
+        };
+        let entries = fs::read_dir(&dir).map_err(|e| e.to_string())?;
+        let mut removed = 0;
+        for entry in entries.flatten() {
+            if entry.path().extension().is_some_and(|ext| ext == "json") {
+                fs::remove_file(entry.path()).map_err(|e| e.to_string())?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
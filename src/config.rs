@@ -7,6 +7,18 @@ use colored::*;
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
     pub environments: std::collections::HashMap<String, EnvironmentConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_ttl: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub theme: Option<ThemeConfig>,
+    #[serde(default, skip_serializing_if = "std::collections::HashMap::is_empty")]
+    pub aliases: std::collections::HashMap<String, AliasConfig>,
+    #[serde(default, skip_serializing_if = "std::collections::HashMap::is_empty")]
+    pub decode: std::collections::HashMap<String, std::collections::HashMap<String, CodeLabels>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http: Option<HttpConfig>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -15,8 +27,61 @@ pub struct EnvironmentConfig {
     pub org_id: String,
     pub entrypoint: String,
     pub environment: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub redact: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redact_fields: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http: Option<HttpConfig>,
 }
 
+// Color names for syntax highlighting, e.g. `key: blue` or `null: bright red`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ThemeConfig {
+    pub key: Option<String>,
+    pub string: Option<String>,
+    pub number: Option<String>,
+    pub boolean: Option<String>,
+    pub null: Option<String>,
+    pub punctuation: Option<String>,
+}
+
+// Either `alias: Field` for every endpoint, or `endpoint: { alias: Field }` for one endpoint.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum AliasConfig {
+    Field(String),
+    Endpoint(std::collections::HashMap<String, String>),
+}
+
+// Retries of failed requests, e.g. `retry: { retries: 5, max_delay_ms: 60000, rate_limit: 2 }`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RetryConfig {
+    pub retries: Option<u32>,
+    pub base_delay_ms: Option<u64>,
+    pub max_delay_ms: Option<u64>,
+    // Requests per second, across every request the command makes.
+    pub rate_limit: Option<f64>,
+}
+
+// HTTP client settings, globally or for one configuration, e.g.
+// `http: { proxy: http://proxy.corp:8080, ca_bundle: /etc/ssl/corp-ca.pem, connect_timeout: 5 }`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct HttpConfig {
+    // In seconds.
+    pub connect_timeout: Option<u64>,
+    pub read_timeout: Option<u64>,
+    pub proxy: Option<String>,
+    pub ca_bundle: Option<String>,
+    pub gzip: Option<bool>,
+    pub user_agent: Option<String>,
+}
+
+// Labels for the codes of one field, e.g. `TransStatus: { 7: Disputed }`.
+pub type CodeLabels = std::collections::HashMap<String, String>;
+
 impl Config {
     pub fn load() -> Self {
         let config_path = Self::get_config_path();
@@ -28,6 +93,12 @@ impl Config {
         }
     }
 
+    // Loads the config file without offering to create one, for commands that work without it.
+    pub fn load_existing() -> Option<Self> {
+        let config_content = fs::read_to_string(Self::get_config_path()).ok()?;
+        Some(serde_yaml::from_str(&config_content).expect("Failed to parse config file"))
+    }
+
     fn get_config_path() -> PathBuf {
         dirs::home_dir().expect("Failed to get home directory").join("payquery.yml")
     }
@@ -40,6 +111,12 @@ impl Config {
 
         let config = Config {
             environments,
+            cache_ttl: None,
+            theme: None,
+            aliases: std::collections::HashMap::new(),
+            decode: std::collections::HashMap::new(),
+            retry: None,
+            http: None,
         };
 
         let config_path = Self::get_config_path();
@@ -81,6 +158,10 @@ impl Config {
             org_id,
             entrypoint,
             environment,
+            timezone: None,
+            redact: false,
+            redact_fields: Vec::new(),
+            http: None,
         }
     }
 
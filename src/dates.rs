@@ -0,0 +1,345 @@
+use std::str::FromStr;
+use chrono::{DateTime, Datelike, Duration, Month, Months, NaiveDate, NaiveDateTime, NaiveTime, Offset, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use regex::Regex;
+
+// Timestamps are sent to the API in UTC, without an offset.
+const API_DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.3f";
+
+pub fn parse_timezone(name: &str) -> Result<Tz, String> {
+    Tz::from_str(name).map_err(|_| format!("Unknown time zone '{}' (use an IANA name such as America/New_York)", name))
+}
+
+pub fn to_api_timestamp(datetime: DateTime<Tz>) -> String {
+    datetime.with_timezone(&Utc).format(API_DATE_FORMAT).to_string()
+}
+
+// Reads a wall-clock time in `tz`. Times skipped by a DST change are read with the UTC offset
+// in effect before the gap, which moves them past it (02:30 becomes 03:30 when clocks spring
+// forward at 02:00).
+fn localize(local: NaiveDateTime, tz: Tz) -> DateTime<Tz> {
+    tz.from_local_datetime(&local).earliest().unwrap_or_else(|| {
+        let before_gap = tz.offset_from_utc_datetime(&(local - Duration::days(1))).fix();
+        tz.from_utc_datetime(&(local - Duration::seconds(before_gap.local_minus_utc() as i64)))
+    })
+}
+
+fn add_months(date: NaiveDate, months: i64) -> Option<NaiveDate> {
+    let step = Months::new(u32::try_from(months.unsigned_abs()).ok()?);
+    if months >= 0 { date.checked_add_months(step) } else { date.checked_sub_months(step) }
+}
+
+// Calendar periods as [start, end) date pairs, relative to `today`.
+fn period_ranges(today: NaiveDate) -> Vec<(&'static str, NaiveDate, NaiveDate)> {
+    let day = |date: NaiveDate, days: i64| date + Duration::days(days);
+    let month = |date: NaiveDate, months: i64| add_months(date, months).unwrap();
+
+    let start_of_week = day(today, -(today.weekday().num_days_from_monday() as i64));
+    let start_of_month = today.with_day(1).unwrap();
+    let start_of_quarter = NaiveDate::from_ymd_opt(today.year(), (today.month0() / 3) * 3 + 1, 1).unwrap();
+    let start_of_year = today.with_ordinal(1).unwrap();
+
+    vec![
+        ("today", today, day(today, 1)),
+        ("yesterday", day(today, -1), today),
+        ("tomorrow", day(today, 1), day(today, 2)),
+        ("this week", start_of_week, day(start_of_week, 7)),
+        ("next week", day(start_of_week, 7), day(start_of_week, 14)),
+        ("last week", day(start_of_week, -7), start_of_week),
+        ("this month", start_of_month, month(start_of_month, 1)),
+        ("next month", month(start_of_month, 1), month(start_of_month, 2)),
+        ("last month", month(start_of_month, -1), start_of_month),
+        ("this quarter", start_of_quarter, month(start_of_quarter, 3)),
+        ("next quarter", month(start_of_quarter, 3), month(start_of_quarter, 6)),
+        ("last quarter", month(start_of_quarter, -3), start_of_quarter),
+        ("this year", start_of_year, month(start_of_year, 12)),
+        ("next year", month(start_of_year, 12), month(start_of_year, 24)),
+        ("last year", month(start_of_year, -12), start_of_year),
+    ]
+}
+
+// Looks up a period such as "last month"; a bare unit ("week") means the current one.
+fn find_period(words: &[&str], today: NaiveDate) -> Option<(NaiveDate, NaiveDate)> {
+    let name = match words {
+        [unit] if matches!(*unit, "week" | "month" | "quarter" | "year") => format!("this {}", unit),
+        _ => words.join(" "),
+    };
+    period_ranges(today).into_iter()
+        .find(|(period, _, _)| *period == name)
+        .map(|(_, start, end)| (start, end))
+}
+
+fn shift(datetime: DateTime<Tz>, amount: i64, unit: &str) -> Option<DateTime<Tz>> {
+    let tz = datetime.timezone();
+    let local = datetime.naive_local();
+    let unit = if unit.len() > 3 { unit.strip_suffix('s').unwrap_or(unit) } else { unit };
+    match unit {
+        "s" | "sec" | "second" => datetime.checked_add_signed(Duration::try_seconds(amount)?),
+        // `m` is minutes, as in `watch 5m`; months are `mo`.
+        "m" | "min" | "minute" => datetime.checked_add_signed(Duration::try_minutes(amount)?),
+        "h" | "hour" => datetime.checked_add_signed(Duration::try_hours(amount)?),
+        "d" | "day" => Some(localize(local.checked_add_signed(Duration::try_days(amount)?)?, tz)),
+        "w" | "week" => Some(localize(local.checked_add_signed(Duration::try_weeks(amount)?)?, tz)),
+        "mo" | "month" => Some(localize(add_months(local.date(), amount)?.and_time(local.time()), tz)),
+        "y" | "year" => Some(localize(add_months(local.date(), amount.checked_mul(12)?)?.and_time(local.time()), tz)),
+        _ => None,
+    }
+}
+
+// Only full weekday names are recognized, so values like "Sat" or "Mon" are left alone.
+fn parse_weekday(word: &str) -> Option<Weekday> {
+    match word {
+        "monday" | "tuesday" | "wednesday" | "thursday" | "friday" | "saturday" | "sunday" => word.parse().ok(),
+        _ => None,
+    }
+}
+
+fn weekday_date(today: NaiveDate, weekday: Weekday, which: &str) -> Option<NaiveDate> {
+    let today_index = today.weekday().num_days_from_monday() as i64;
+    let target_index = weekday.num_days_from_monday() as i64;
+    let offset = match which {
+        "this" => target_index - today_index,
+        "last" => -((today_index - target_index - 1).rem_euclid(7) + 1),
+        "next" => (target_index - today_index - 1).rem_euclid(7) + 1,
+        _ => return None,
+    };
+    Some(today + Duration::days(offset))
+}
+
+fn parse_time(word: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(word, "%H:%M").ok()
+}
+
+// "Oct 15", "October 15 2024", "Oct 15 @ 14:30" or "Oct 15 2024 @ 14:30".
+fn parse_month_date(words: &[&str], today: NaiveDate) -> Option<NaiveDateTime> {
+    let (month, day, year, time) = match words {
+        [month, day] => (month, day, None, None),
+        [month, day, year] => (month, day, Some(year), None),
+        [month, day, "@", time] => (month, day, None, Some(time)),
+        [month, day, year, "@", time] => (month, day, Some(year), Some(time)),
+        _ => return None,
+    };
+    let month = Month::from_str(month).ok()?;
+    let year = match year {
+        Some(year) if year.len() == 4 => year.parse().ok()?,
+        Some(_) => return None,
+        None => today.year(),
+    };
+    let date = NaiveDate::from_ymd_opt(year, month.number_from_month(), day.parse().ok()?)?;
+    let time = match time {
+        Some(time) => parse_time(time)?,
+        None => NaiveTime::MIN,
+    };
+    Some(date.and_time(time))
+}
+
+// ISO dates and times without milliseconds. Timestamps with milliseconds are already in the
+// API's format and are passed through unchanged.
+fn parse_iso(word: &str) -> Option<NaiveDateTime> {
+    if let Ok(date) = NaiveDate::parse_from_str(word, "%Y-%m-%d") {
+        return Some(date.and_time(NaiveTime::MIN));
+    }
+    ["%Y-%m-%dT%H:%M", "%Y-%m-%dT%H:%M:%S"].iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(word, format).ok())
+}
+
+// Offsets such as "-2w", "today-7d" or "now+1d-2h".
+fn parse_offsets(word: &str, now: DateTime<Tz>) -> Option<DateTime<Tz>> {
+    let offset_re = Regex::new(r"([+-])(\d+)([a-z]+)").unwrap();
+    let start = word.find(['+', '-']).filter(|&pos| {
+        pos == 0 || matches!(&word[..pos], "now" | "today" | "yesterday" | "tomorrow")
+    })?;
+    if !Regex::new(r"^(?:[+-]\d+[a-z]+)+$").unwrap().is_match(&word[start..]) {
+        return None;
+    }
+    let base = match &word[..start] {
+        "" | "now" => now,
+        period => localize(find_period(&[period], now.date_naive())?.0.and_time(NaiveTime::MIN), now.timezone()),
+    };
+    offset_re.captures_iter(&word[start..]).try_fold(base, |datetime, cap| {
+        let amount: i64 = cap[2].parse().ok()?;
+        shift(datetime, if &cap[1] == "-" { -amount } else { amount }, &cap[3])
+    })
+}
+
+fn parse_words(words: &[&str], original: &[&str], now: DateTime<Tz>) -> Option<DateTime<Tz>> {
+    let tz = now.timezone();
+    let today = now.date_naive();
+    let midnight = |date: NaiveDate| localize(date.and_time(NaiveTime::MIN), tz);
+
+    match words {
+        ["now"] => Some(now),
+        ["start", "of", period @ ..] => find_period(period, today).map(|(start, _)| midnight(start)),
+        ["end", "of", period @ ..] => find_period(period, today).map(|(_, end)| midnight(end) - Duration::milliseconds(1)),
+        [amount, unit, "ago"] => shift(now, amount.parse::<i64>().ok()?.checked_neg()?, unit),
+        ["in", amount, unit] => shift(now, amount.parse().ok()?, unit),
+        [which @ ("this" | "last" | "next"), weekday] if parse_weekday(weekday).is_some() => {
+            weekday_date(today, parse_weekday(weekday)?, which).map(midnight)
+        }
+        [weekday] if parse_weekday(weekday).is_some() => weekday_date(today, parse_weekday(weekday)?, "this").map(midnight),
+        [word] => find_period(words, today).map(|(start, _)| midnight(start))
+            .or_else(|| parse_iso(original[0]).map(|local| localize(local, tz)))
+            .or_else(|| parse_offsets(word, now)),
+        _ => find_period(words, today).map(|(start, _)| midnight(start))
+            .or_else(|| parse_month_date(words, today).map(|local| localize(local, tz))),
+    }
+}
+
+// Resolves a whole date expression, e.g. "3 days ago" or "start of quarter", to an API timestamp.
+pub fn parse_date(expression: &str, tz: Tz) -> Option<String> {
+    let now = Utc::now().with_timezone(&tz);
+    let original: Vec<&str> = expression.split_whitespace().collect();
+    let lowercase = expression.to_lowercase();
+    let words: Vec<&str> = lowercase.split_whitespace().collect();
+    parse_words(&words, &original, now).map(to_api_timestamp)
+}
+
+// Midnight of a single day named by `words`: a weekday, an ISO date or a month-name date.
+fn parse_day(words: &[&str], original: &[&str], today: NaiveDate) -> Option<NaiveDate> {
+    match words {
+        [which @ ("this" | "last" | "next"), weekday] => weekday_date(today, parse_weekday(weekday)?, which),
+        [weekday] if parse_weekday(weekday).is_some() => weekday_date(today, parse_weekday(weekday)?, "this"),
+        [_] => NaiveDate::parse_from_str(original[0], "%Y-%m-%d").ok(),
+        [_, _] | [_, _, _] => parse_month_date(words, today).map(|local| local.date()),
+        _ => None,
+    }
+}
+
+// Resolves a period, e.g. "last week", "yesterday", "friday", "October" or "2025", to its
+// [start, end) pair of API timestamps.
+pub fn parse_period(expression: &str, tz: Tz) -> Option<(String, String)> {
+    let today = Utc::now().with_timezone(&tz).date_naive();
+    let original: Vec<&str> = expression.split_whitespace().collect();
+    let lowercase = expression.to_lowercase();
+    let words: Vec<&str> = lowercase.split_whitespace().collect();
+
+    let (start, end) = find_period(&words, today)
+        .or_else(|| parse_day(&words, &original, today).map(|day| (day, day + Duration::days(1))))
+        .or_else(|| match words.as_slice() {
+            [year] if year.len() == 4 => {
+                let start = NaiveDate::from_ymd_opt(year.parse().ok()?, 1, 1)?;
+                Some((start, add_months(start, 12)?))
+            }
+            [month] => {
+                let month = Month::from_str(month).ok()?;
+                let start = NaiveDate::from_ymd_opt(today.year(), month.number_from_month(), 1)?;
+                Some((start, add_months(start, 1)?))
+            }
+            _ => None,
+        })?;
+    let midnight = |date: NaiveDate| to_api_timestamp(localize(date.and_time(NaiveTime::MIN), tz));
+    Some((midnight(start), midnight(end)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now(tz: Tz) -> DateTime<Tz> {
+        // Wednesday, 2024-10-16 at 10:30 local time.
+        localize(NaiveDate::from_ymd_opt(2024, 10, 16).unwrap().and_hms_opt(10, 30, 0).unwrap(), tz)
+    }
+
+    fn resolve(expression: &str, tz: Tz) -> Option<String> {
+        let original: Vec<&str> = expression.split_whitespace().collect();
+        let lowercase = expression.to_lowercase();
+        let words: Vec<&str> = lowercase.split_whitespace().collect();
+        parse_words(&words, &original, now(tz)).map(to_api_timestamp)
+    }
+
+    #[test]
+    fn relative_expressions() {
+        assert_eq!(resolve("now", Tz::UTC).unwrap(), "2024-10-16T10:30:00.000");
+        assert_eq!(resolve("3 days ago", Tz::UTC).unwrap(), "2024-10-13T10:30:00.000");
+        assert_eq!(resolve("in 2 weeks", Tz::UTC).unwrap(), "2024-10-30T10:30:00.000");
+        assert_eq!(resolve("6 hours ago", Tz::UTC).unwrap(), "2024-10-16T04:30:00.000");
+        assert_eq!(resolve("1 month ago", Tz::UTC).unwrap(), "2024-09-16T10:30:00.000");
+    }
+
+    #[test]
+    fn offsets() {
+        assert_eq!(resolve("-2w", Tz::UTC).unwrap(), "2024-10-02T10:30:00.000");
+        assert_eq!(resolve("today-7d", Tz::UTC).unwrap(), "2024-10-09T00:00:00.000");
+        assert_eq!(resolve("now+1d-2h", Tz::UTC).unwrap(), "2024-10-17T08:30:00.000");
+        assert_eq!(resolve("now-5m", Tz::UTC).unwrap(), "2024-10-16T10:25:00.000");
+        assert_eq!(resolve("now-5mo", Tz::UTC).unwrap(), "2024-05-16T10:30:00.000");
+        assert_eq!(resolve("-1y", Tz::UTC).unwrap(), "2023-10-16T10:30:00.000");
+        assert_eq!(resolve("later-1d", Tz::UTC), None);
+        assert_eq!(resolve("now-5x", Tz::UTC), None);
+    }
+
+    #[test]
+    fn overflowing_offsets_are_rejected() {
+        assert_eq!(resolve("1000000000 days ago", Tz::UTC), None);
+        assert_eq!(resolve("99999999999999 seconds ago", Tz::UTC), None);
+        assert_eq!(resolve("-9223372036854775808 days ago", Tz::UTC), None);
+        assert_eq!(resolve("now+9999999999w", Tz::UTC), None);
+    }
+
+    #[test]
+    fn periods() {
+        assert_eq!(resolve("today", Tz::UTC).unwrap(), "2024-10-16T00:00:00.000");
+        assert_eq!(resolve("last week", Tz::UTC).unwrap(), "2024-10-07T00:00:00.000");
+        assert_eq!(resolve("start of quarter", Tz::UTC).unwrap(), "2024-10-01T00:00:00.000");
+        assert_eq!(resolve("end of last month", Tz::UTC).unwrap(), "2024-09-30T23:59:59.999");
+        assert_eq!(resolve("next year", Tz::UTC).unwrap(), "2025-01-01T00:00:00.000");
+    }
+
+    #[test]
+    fn weekdays() {
+        assert_eq!(resolve("friday", Tz::UTC).unwrap(), "2024-10-18T00:00:00.000");
+        assert_eq!(resolve("last friday", Tz::UTC).unwrap(), "2024-10-11T00:00:00.000");
+        assert_eq!(resolve("next wednesday", Tz::UTC).unwrap(), "2024-10-23T00:00:00.000");
+        assert_eq!(resolve("last wednesday", Tz::UTC).unwrap(), "2024-10-09T00:00:00.000");
+        assert_eq!(resolve("sat", Tz::UTC), None);
+    }
+
+    #[test]
+    fn calendar_dates() {
+        assert_eq!(resolve("2024-10-15", Tz::UTC).unwrap(), "2024-10-15T00:00:00.000");
+        assert_eq!(resolve("2024-10-15T14:30", Tz::UTC).unwrap(), "2024-10-15T14:30:00.000");
+        assert_eq!(resolve("2024-10-15T14:30:15", Tz::UTC).unwrap(), "2024-10-15T14:30:15.000");
+        assert_eq!(resolve("Oct 15", Tz::UTC).unwrap(), "2024-10-15T00:00:00.000");
+        assert_eq!(resolve("October 15 2023 @ 14:30", Tz::UTC).unwrap(), "2023-10-15T14:30:00.000");
+        assert_eq!(resolve("Oct 32", Tz::UTC), None);
+    }
+
+    #[test]
+    fn time_zones() {
+        let new_york: Tz = "America/New_York".parse().unwrap();
+        assert_eq!(resolve("today", new_york).unwrap(), "2024-10-16T04:00:00.000");
+        assert_eq!(resolve("2024-10-15T14:30", new_york).unwrap(), "2024-10-15T18:30:00.000");
+    }
+
+    #[test]
+    fn times_skipped_by_dst_move_past_the_gap() {
+        let new_york: Tz = "America/New_York".parse().unwrap();
+        assert_eq!(resolve("2024-03-10T02:30", new_york).unwrap(), "2024-03-10T07:30:00.000");
+
+        // Clocks in Santiago jump from midnight to 01:00, so that day starts at 01:00 and is 23 hours long.
+        let santiago: Tz = "America/Santiago".parse().unwrap();
+        let (start, end) = parse_period("2026-09-06", santiago).unwrap();
+        assert_eq!(start, "2026-09-06T04:00:00.000");
+        assert_eq!(end, "2026-09-07T03:00:00.000");
+    }
+
+    #[test]
+    fn repeated_times_use_the_first_occurrence() {
+        let new_york: Tz = "America/New_York".parse().unwrap();
+        assert_eq!(resolve("2024-11-03T01:30", new_york).unwrap(), "2024-11-03T05:30:00.000");
+    }
+
+    #[test]
+    fn day_arithmetic_keeps_the_wall_clock_time_across_dst() {
+        let new_york: Tz = "America/New_York".parse().unwrap();
+        let before = localize(NaiveDate::from_ymd_opt(2024, 3, 9).unwrap().and_hms_opt(12, 0, 0).unwrap(), new_york);
+        assert_eq!(to_api_timestamp(shift(before, 1, "day").unwrap()), "2024-03-10T16:00:00.000");
+        assert_eq!(to_api_timestamp(shift(before, 24, "hours").unwrap()), "2024-03-10T17:00:00.000");
+    }
+
+    #[test]
+    fn periods_by_name() {
+        assert_eq!(parse_period("2025", Tz::UTC).unwrap(), ("2025-01-01T00:00:00.000".to_string(), "2026-01-01T00:00:00.000".to_string()));
+        assert!(parse_period("someday", Tz::UTC).is_none());
+    }
+}
@@ -0,0 +1,155 @@
+use std::sync::OnceLock;
+use serde_json::Value;
+use crate::config::Config;
+use crate::path::is_simple_field;
+
+type Labels = &'static [(&'static str, &'static str)];
+
+const SETTLEMENT_STATUS: Labels = &[("-5", "Chargeback"), ("-2", "Rejected"), ("-1", "Exception"), ("0", "Pending"), ("1", "In Transit"), ("2", "Transferred"), ("3", "Funded"), ("5", "Released")];
+
+// Status codes used by the Query API, per endpoint. The first field is the record field and the
+// others are filter names that take the same codes.
+const BUILT_IN_CODES: &[(&str, &[&str], Labels)] = &[
+    ("transactions", &["TransStatus", "status"], &[("0", "In Process"), ("1", "Approved"), ("2", "Hold"), ("3", "Voided"), ("4", "Declined"), ("5", "Refunded")]),
+    ("transactions", &["SettlementStatus"], SETTLEMENT_STATUS),
+    ("settlements", &["SettlementStatus", "status"], SETTLEMENT_STATUS),
+    ("batches", &["BatchStatus", "status"], &[("0", "Open"), ("1", "Closed"), ("2", "Settled")]),
+    ("chargebacks", &["Status", "status"], &[("0", "Open"), ("1", "Pending"), ("2", "Won"), ("3", "Lost"), ("4", "Accepted")]),
+    ("payouts", &["Status", "status"], &[("0", "Cancelled"), ("1", "Captured"), ("2", "Processing"), ("3", "Processed"), ("4", "Paid"), ("5", "Error")]),
+    ("subscriptions", &["SubStatus", "status"], &[("0", "Paused"), ("1", "Active"), ("2", "Cancelled"), ("3", "Finished")]),
+];
+
+struct CodeTable {
+    fields: Vec<String>,
+    labels: Vec<(String, String)>,
+}
+
+pub struct Decoder {
+    tables: Vec<CodeTable>,
+}
+
+static DECODER: OnceLock<Decoder> = OnceLock::new();
+
+// Labels are compared without case, spaces, dashes or underscores, so `in_transit` matches "In Transit".
+fn normalize(label: &str) -> String {
+    label.chars().filter(|c| !matches!(c, ' ' | '-' | '_')).flat_map(char::to_lowercase).collect()
+}
+
+impl Decoder {
+    // The built-in codes of `endpoint`, plus the `decode` section of payquery.yml, e.g.
+    // `decode: { transactions: { TransStatus: { 7: Disputed } } }`.
+    pub fn load(endpoint: Option<&str>, config: Option<&Config>) -> Self {
+        let is_endpoint = |name: &str| endpoint.is_some_and(|endpoint| endpoint.eq_ignore_ascii_case(name));
+        let mut decoder = Decoder { tables: Vec::new() };
+
+        for (_, fields, labels) in BUILT_IN_CODES.iter().filter(|(name, _, _)| is_endpoint(name)) {
+            decoder.tables.push(CodeTable {
+                fields: fields.iter().map(|field| field.to_string()).collect(),
+                labels: labels.iter().map(|(code, label)| (code.to_string(), label.to_string())).collect(),
+            });
+        }
+        let user_codes = config.iter().flat_map(|config| &config.decode).filter(|(name, _)| is_endpoint(name));
+        for (field, labels) in user_codes.flat_map(|(_, fields)| fields) {
+            let position = decoder.tables.iter().position(|table| table.fields.iter().any(|name| name.eq_ignore_ascii_case(field)));
+            let index = position.unwrap_or_else(|| {
+                decoder.tables.push(CodeTable { fields: vec![field.clone()], labels: Vec::new() });
+                decoder.tables.len() - 1
+            });
+            let table = &mut decoder.tables[index];
+            for (code, label) in labels {
+                table.labels.retain(|(existing, _)| existing != code);
+                table.labels.push((code.clone(), label.clone()));
+            }
+        }
+        decoder
+    }
+
+    // Matches on the last segment of a path, so `Transaction.TransStatus` is decoded as well.
+    fn table(&self, field: &str) -> Option<&CodeTable> {
+        let name = field.rsplit('.').next().unwrap_or(field);
+        self.tables.iter().find(|table| table.fields.iter().any(|field| field.eq_ignore_ascii_case(name)))
+    }
+
+    // Replaces labels in `eq`, `ne`, `in` and `nin` filters on coded fields with their codes,
+    // so `status eq approved` is sent as `status(eq)=1`.
+    pub fn encode_filters(&self, filters: Vec<(String, String)>) -> Result<Vec<(String, String)>, String> {
+        filters.into_iter()
+            .map(|(key, value)| {
+                let Some((field, condition)) = key.strip_suffix(')').and_then(|key| key.split_once('(')) else {
+                    return Ok((key, value));
+                };
+                let Some(table) = self.table(field).filter(|_| matches!(condition, "eq" | "ne" | "in" | "nin")) else {
+                    return Ok((key, value));
+                };
+                let codes = value.split('|')
+                    .map(|item| {
+                        if item.parse::<i64>().is_ok() {
+                            return Ok(item.to_string());
+                        }
+                        table.labels.iter()
+                            .find(|(_, label)| normalize(label) == normalize(item))
+                            .map(|(code, _)| code.clone())
+                            .ok_or_else(|| {
+                                let labels: Vec<String> = table.labels.iter().map(|(_, label)| normalize(label)).collect();
+                                format!("Unknown value '{}' for '{}' (use a code or one of: {})", item, field, labels.join(", "))
+                            })
+                    })
+                    .collect::<Result<Vec<_>, String>>()?;
+                Ok((key, codes.join("|")))
+            })
+            .collect()
+    }
+
+    // Filters evaluated against the records themselves can't use API filter names such as
+    // `status`, so those are replaced with the record field they stand for (`TransStatus`).
+    pub fn record_filters(&self, filters: Vec<(String, String)>) -> Vec<(String, String)> {
+        filters.into_iter()
+            .map(|(key, value)| match key.strip_suffix(')').and_then(|key| key.split_once('(')) {
+                Some((field, condition)) if is_simple_field(field) => match self.table(field) {
+                    Some(table) => (format!("{}({})", table.fields[0], condition), value),
+                    None => (key, value),
+                },
+                _ => (key, value),
+            })
+            .collect()
+    }
+
+    fn decode(&self, value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                for (key, child) in map.iter_mut() {
+                    let code = match &*child {
+                        Value::Number(number) => number.to_string(),
+                        Value::String(text) => text.clone(),
+                        _ => String::new(),
+                    };
+                    let label = self.table(key).and_then(|table| table.labels.iter().find(|(known, _)| *known == code));
+                    match label {
+                        Some((code, label)) => *child = Value::String(format!("{} ({})", code, label)),
+                        None => self.decode(child),
+                    }
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(|item| self.decode(item)),
+            _ => {}
+        }
+    }
+}
+
+// Turns on `--decode`, which shows coded fields as `1 (Approved)` in every output format.
+pub fn configure_decoding(args: &[String], decoder: Decoder) {
+    if args.contains(&"--decode".to_string()) {
+        let _ = DECODER.set(decoder);
+    }
+}
+
+pub fn decode_record(mut record: Value) -> Value {
+    if let Some(decoder) = DECODER.get() {
+        decoder.decode(&mut record);
+    }
+    record
+}
+
+pub fn decode_records(records: Vec<Value>) -> Vec<Value> {
+    records.into_iter().map(decode_record).collect()
+}
@@ -0,0 +1,143 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde_json::Value;
+use crate::args::{clause_position, record_key, subcommand_position};
+use crate::records::{parse_records, flatten};
+use crate::redact::{is_redacting, redact_field};
+
+pub enum DiffTarget {
+    Config(String),
+    Snapshot(PathBuf),
+}
+
+pub struct FieldChange {
+    pub field: String,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+pub struct RecordDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<(String, Vec<FieldChange>)>,
+}
+
+// Like `watch`, `diff` is only recognized before the endpoint, and `against` is not looked
+// for in quoted filter values.
+pub fn extract_diff_clause(args: &[String]) -> Result<(Vec<String>, Option<DiffTarget>), String> {
+    let Some(pos) = subcommand_position(args).filter(|&pos| args[pos] == "diff") else {
+        return Ok((args.to_vec(), None));
+    };
+    let against_pos = clause_position(args, "against")
+        .ok_or("Missing 'against' clause (e.g. 'diff transactions for qa against sandbox')")?;
+    let target = args.get(against_pos + 1).ok_or("Missing configuration or snapshot file after 'against'")?;
+
+    let path = Path::new(target);
+    let target = if target.ends_with(".json") || path.is_file() {
+        DiffTarget::Snapshot(path.to_path_buf())
+    } else {
+        DiffTarget::Config(target.clone())
+    };
+
+    let remaining = args.iter()
+        .enumerate()
+        .filter(|(i, _)| *i != pos && *i != against_pos && *i != against_pos + 1)
+        .map(|(_, arg)| arg.clone())
+        .collect();
+
+    Ok((remaining, Some(target)))
+}
+
+pub fn load_snapshot(path: &Path) -> Result<Vec<Value>, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("Error reading snapshot '{}': {}", path.display(), e))?;
+    parse_records(&content).map_err(|e| format!("Error parsing snapshot '{}': {}", path.display(), e))
+}
+
+fn compare_fields(before: &Value, after: &Value) -> Vec<FieldChange> {
+    let mut before_fields = BTreeMap::new();
+    let mut after_fields = BTreeMap::new();
+    flatten(before, "", &mut before_fields);
+    flatten(after, "", &mut after_fields);
+
+    let mut fields: Vec<&String> = before_fields.keys().chain(after_fields.keys()).collect();
+    fields.sort();
+    fields.dedup();
+
+    fields.into_iter()
+        .filter(|field| before_fields.get(*field) != after_fields.get(*field))
+        .map(|field| FieldChange {
+            field: field.clone(),
+            before: before_fields.get(field).cloned(),
+            after: after_fields.get(field).cloned(),
+        })
+        .collect()
+}
+
+pub fn compare(baseline: &[Value], current: &[Value], key: Option<&str>) -> RecordDiff {
+    let baseline_by_key: HashMap<String, &Value> = baseline.iter().map(|record| (record_key(record, key), record)).collect();
+    let current_by_key: HashMap<String, &Value> = current.iter().map(|record| (record_key(record, key), record)).collect();
+
+    let added = current.iter()
+        .map(|record| record_key(record, key))
+        .filter(|id| !baseline_by_key.contains_key(id))
+        .collect();
+    let removed = baseline.iter()
+        .map(|record| record_key(record, key))
+        .filter(|id| !current_by_key.contains_key(id))
+        .collect();
+    let changed = baseline.iter()
+        .filter_map(|record| {
+            let id = record_key(record, key);
+            let changes = compare_fields(record, current_by_key.get(&id)?);
+            (!changes.is_empty()).then_some((id, changes))
+        })
+        .collect();
+
+    RecordDiff { added, removed, changed }
+}
+
+// Records are compared on their real values, so with `--redact` the changed values are masked
+// afterwards, and so are ids read from a redacted field.
+pub fn redact_diff(diff: RecordDiff, baseline: &[Value], current: &[Value], key: Option<&str>) -> RecordDiff {
+    if !is_redacting() {
+        return diff;
+    }
+    let ids: HashMap<String, String> = baseline.iter().chain(current)
+        .map(|record| (record_key(record, key), record_key(&redact_field("", record.clone()), key)))
+        .collect();
+    let redact_id = |id: String| ids.get(&id).cloned().unwrap_or(id);
+    let redact_value = |field: &str, value: Option<Value>| value.map(|value| redact_field(field, value));
+
+    RecordDiff {
+        added: diff.added.into_iter().map(redact_id).collect(),
+        removed: diff.removed.into_iter().map(redact_id).collect(),
+        changed: diff.changed.into_iter()
+            .map(|(id, changes)| {
+                let changes = changes.into_iter()
+                    .map(|change| FieldChange {
+                        before: redact_value(&change.field, change.before),
+                        after: redact_value(&change.field, change.after),
+                        field: change.field,
+                    })
+                    .collect();
+                (redact_id(id), changes)
+            })
+            .collect(),
+    }
+}
+
+fn display_value(value: &Option<Value>) -> String {
+    value.as_ref().map_or("(missing)".to_string(), |v| v.to_string())
+}
+
+pub fn format_diff(diff: &RecordDiff) -> String {
+    let mut lines = Vec::new();
+    lines.extend(diff.added.iter().map(|id| format!("+ {}", id)));
+    lines.extend(diff.removed.iter().map(|id| format!("- {}", id)));
+    for (id, changes) in &diff.changed {
+        lines.push(format!("~ {}", id));
+        lines.extend(changes.iter().map(|change| format!("    {}: {} → {}", change.field, display_value(&change.before), display_value(&change.after))));
+    }
+    lines.join("\n")
+}
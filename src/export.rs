@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde_json::Value;
+use crate::args::clause_position;
+use crate::records::flatten;
+
+#[derive(Clone, Copy)]
+pub enum ExportFormat {
+    Json,
+    Ndjson,
+    Yaml,
+    Csv,
+}
+
+pub struct Export {
+    pub path: PathBuf,
+    pub format: ExportFormat,
+}
+
+impl ExportFormat {
+    pub fn from_path(path: &Path) -> Result<Self, String> {
+        let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("").to_lowercase();
+        match extension.as_str() {
+            "json" => Ok(ExportFormat::Json),
+            "ndjson" | "jsonl" => Ok(ExportFormat::Ndjson),
+            "yaml" | "yml" => Ok(ExportFormat::Yaml),
+            "csv" => Ok(ExportFormat::Csv),
+            _ => Err(format!("Cannot tell the export format of '{}' (use .json, .ndjson, .yaml or .csv)", path.display())),
+        }
+    }
+}
+
+// Handles `--out FILE`, `into FILE` and `--force`.
+pub fn extract_export_clause(args: &[String]) -> Result<(Vec<String>, Option<Export>), String> {
+    let mut remaining = Vec::new();
+    let mut target = None;
+    let mut force = false;
+    let into_pos = clause_position(args, "into");
+    let mut iter = args.iter().enumerate();
+    while let Some((i, arg)) = iter.next() {
+        match arg.as_str() {
+            "into" if Some(i) != into_pos => remaining.push(arg.clone()),
+            "--out" | "into" => target = Some(iter.next().ok_or_else(|| format!("Missing file name after '{}'", arg))?.1.clone()),
+            "--force" => force = true,
+            _ => remaining.push(arg.clone()),
+        }
+    }
+
+    let Some(target) = target else {
+        return Ok((remaining, None));
+    };
+    let path = PathBuf::from(target);
+    if path.exists() && !force {
+        return Err(format!("'{}' already exists (use --force to overwrite)", path.display()));
+    }
+    let format = ExportFormat::from_path(&path)?;
+    Ok((remaining, Some(Export { path, format })))
+}
+
+fn csv_field(value: Option<&Value>) -> String {
+    let text = match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(s)) => s.clone(),
+        Some(other) => other.to_string(),
+    };
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+fn render_csv(records: &[Value]) -> String {
+    let rows: Vec<BTreeMap<String, Value>> = records.iter()
+        .map(|record| {
+            let mut row = BTreeMap::new();
+            match record {
+                Value::Object(_) => flatten(record, "", &mut row),
+                other => {
+                    row.insert("value".to_string(), other.clone());
+                }
+            }
+            row
+        })
+        .collect();
+
+    let mut columns: Vec<&String> = rows.iter().flat_map(|row| row.keys()).collect();
+    columns.sort();
+    columns.dedup();
+
+    let mut lines = vec![columns.iter().map(|column| csv_field(Some(&Value::String(column.to_string())))).collect::<Vec<_>>().join(",")];
+    lines.extend(rows.iter().map(|row| columns.iter().map(|column| csv_field(row.get(*column))).collect::<Vec<_>>().join(",")));
+    lines.join("\n") + "\n"
+}
+
+pub fn render(records: &[Value], format: ExportFormat) -> Result<String, String> {
+    match format {
+        ExportFormat::Json => serde_json::to_string_pretty(records).map(|text| text + "\n").map_err(|e| e.to_string()),
+        ExportFormat::Ndjson => records.iter()
+            .map(|record| serde_json::to_string(record).map(|line| line + "\n").map_err(|e| e.to_string()))
+            .collect(),
+        ExportFormat::Yaml => serde_yaml::to_string(records).map_err(|e| e.to_string()),
+        ExportFormat::Csv => Ok(render_csv(records)),
+    }
+}
+
+// Writes to a temporary file next to the target and renames it, so readers never see a partial export.
+pub fn write_export(export: &Export, records: &[Value]) -> Result<(), String> {
+    let content = render(records, export.format)?;
+    let file_name = export.path.file_name().ok_or_else(|| format!("Invalid export path '{}'", export.path.display()))?;
+    let tmp_path = export.path.with_file_name(format!(".{}.tmp", file_name.to_string_lossy()));
+    fs::write(&tmp_path, content).map_err(|e| format!("Error writing '{}': {}", tmp_path.display(), e))?;
+    fs::rename(&tmp_path, &export.path).map_err(|e| {
+        let _ = fs::remove_file(&tmp_path);
+        format!("Error writing '{}': {}", export.path.display(), e)
+    })
+}
@@ -0,0 +1,184 @@
+use std::str::FromStr;
+use std::sync::OnceLock;
+use colored::*;
+use crate::config::ThemeConfig;
+
+pub struct Theme {
+    key: Color,
+    string: Color,
+    number: Color,
+    boolean: Color,
+    null: Color,
+    punctuation: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            key: Color::Blue,
+            string: Color::Green,
+            number: Color::Yellow,
+            boolean: Color::Magenta,
+            null: Color::Red,
+            punctuation: Color::White,
+        }
+    }
+}
+
+static THEME: OnceLock<Theme> = OnceLock::new();
+
+fn parse_color(name: &Option<String>, default: Color) -> Result<Color, String> {
+    match name {
+        Some(name) => Color::from_str(name).map_err(|_| format!("Invalid theme color '{}'", name)),
+        None => Ok(default),
+    }
+}
+
+// Applies the `theme` section of payquery.yml. Colors that are not set keep their defaults.
+pub fn set_theme(config: &ThemeConfig) -> Result<(), String> {
+    let default = Theme::default();
+    let theme = Theme {
+        key: parse_color(&config.key, default.key)?,
+        string: parse_color(&config.string, default.string)?,
+        number: parse_color(&config.number, default.number)?,
+        boolean: parse_color(&config.boolean, default.boolean)?,
+        null: parse_color(&config.null, default.null)?,
+        punctuation: parse_color(&config.punctuation, default.punctuation)?,
+    };
+    let _ = THEME.set(theme);
+    Ok(())
+}
+
+fn theme() -> &'static Theme {
+    THEME.get_or_init(Theme::default)
+}
+
+fn paint(text: &str, color: Color) -> String {
+    text.color(color).to_string()
+}
+
+// Expects the output of `serde_json::to_string_pretty`, where strings never span lines.
+pub fn highlight_json(text: &str) -> String {
+    let theme = theme();
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len() * 2);
+    let mut i = 0;
+
+    while i < chars.len() {
+        let start = i;
+        match chars[i] {
+            '"' => {
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    i += if chars[i] == '\\' { 2 } else { 1 };
+                }
+                i = (i + 1).min(chars.len());
+                let token: String = chars[start..i].iter().collect();
+                let is_key = chars[i..].iter().find(|c| !c.is_whitespace()) == Some(&':');
+                out.push_str(&paint(&token, if is_key { theme.key } else { theme.string }));
+            }
+            '-' | '0'..='9' => {
+                while i < chars.len() && matches!(chars[i], '-' | '+' | '.' | 'e' | 'E' | '0'..='9') {
+                    i += 1;
+                }
+                out.push_str(&paint(&chars[start..i].iter().collect::<String>(), theme.number));
+            }
+            c if c.is_ascii_alphabetic() => {
+                while i < chars.len() && chars[i].is_ascii_alphabetic() {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                let color = if word == "null" { theme.null } else { theme.boolean };
+                out.push_str(&paint(&word, color));
+            }
+            c if c.is_whitespace() => {
+                out.push(c);
+                i += 1;
+            }
+            c => {
+                out.push_str(&paint(&c.to_string(), theme.punctuation));
+                i += 1;
+            }
+        }
+    }
+
+    out
+}
+
+fn yaml_scalar(value: &str) -> String {
+    let theme = theme();
+    let color = match value {
+        "null" | "~" => theme.null,
+        "true" | "false" => theme.boolean,
+        "[]" | "{}" => theme.punctuation,
+        _ if value.parse::<f64>().is_ok() => theme.number,
+        _ => theme.string,
+    };
+    paint(value, color)
+}
+
+// Splits `key: value` (or a bare `key:`), skipping over a quoted key.
+fn split_yaml_key(text: &str) -> Option<(&str, &str)> {
+    let search_from = match text.chars().next() {
+        Some(quote @ ('"' | '\'')) => text[1..].find(quote).map(|pos| pos + 2)?,
+        _ => 0,
+    };
+    let rest = &text[search_from..];
+    if let Some(pos) = rest.find(": ") {
+        let split = search_from + pos;
+        Some((&text[..split], &text[split + 2..]))
+    } else if rest.ends_with(':') {
+        Some((&text[..text.len() - 1], ""))
+    } else {
+        None
+    }
+}
+
+// Expects the output of `serde_yaml::to_string`: one mapping entry or list item per line.
+pub fn highlight_yaml(text: &str) -> String {
+    let theme = theme();
+    // Indentation of the key that opened a block scalar (`key: |`); deeper lines are plain text.
+    let mut block_indent: Option<usize> = None;
+
+    text.lines()
+        .map(|line| {
+            let indent = line.len() - line.trim_start().len();
+            let mut rest = &line[indent..];
+            let mut out = line[..indent].to_string();
+
+            if let Some(block) = block_indent {
+                if indent > block || rest.is_empty() {
+                    return out + &paint(rest, theme.string);
+                }
+                block_indent = None;
+            }
+
+            let mut item_indent = indent;
+            while let Some(after_dash) = rest.strip_prefix("- ").or(if rest == "-" { Some("") } else { None }) {
+                out.push_str(&paint("-", theme.punctuation));
+                out.push(' ');
+                rest = after_dash;
+                item_indent += 2;
+            }
+
+            match split_yaml_key(rest) {
+                Some((key, value)) => {
+                    out.push_str(&paint(key, theme.key));
+                    out.push_str(&paint(":", theme.punctuation));
+                    if !value.is_empty() {
+                        out.push(' ');
+                        if value.starts_with('|') || value.starts_with('>') {
+                            block_indent = Some(item_indent);
+                            out.push_str(&paint(value, theme.punctuation));
+                        } else {
+                            out.push_str(&yaml_scalar(value));
+                        }
+                    }
+                }
+                None => out.push_str(&yaml_scalar(rest)),
+            }
+            out
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
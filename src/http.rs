@@ -0,0 +1,92 @@
+use std::fs;
+use std::time::Duration;
+use reqwest::blocking::{Client, Request};
+use reqwest::{Certificate, Proxy};
+use crate::config::{Config, HttpConfig};
+
+const USER_AGENT: &str = concat!("payquery/", env!("CARGO_PKG_VERSION"));
+
+// Settings of the configuration take precedence over the global ones, one by one.
+fn merge(global: Option<&HttpConfig>, environment: Option<&HttpConfig>) -> HttpConfig {
+    let global = global.cloned().unwrap_or_default();
+    let environment = environment.cloned().unwrap_or_default();
+    HttpConfig {
+        connect_timeout: environment.connect_timeout.or(global.connect_timeout),
+        read_timeout: environment.read_timeout.or(global.read_timeout),
+        proxy: environment.proxy.or(global.proxy),
+        ca_bundle: environment.ca_bundle.or(global.ca_bundle),
+        gzip: environment.gzip.or(global.gzip),
+        user_agent: environment.user_agent.or(global.user_agent),
+    }
+}
+
+fn http_config(config: &Config, config_name: &str) -> HttpConfig {
+    let env_config = config.environments.get(config_name);
+    merge(config.http.as_ref(), env_config.and_then(|config| config.http.as_ref()))
+}
+
+pub fn user_agent(config: &Config, config_name: &str) -> String {
+    http_config(config, config_name).user_agent.unwrap_or_else(|| USER_AGENT.to_string())
+}
+
+// Builds the client for the configuration `config_name`. Without settings, requests use
+// reqwest's defaults and the HTTP(S)_PROXY environment variables.
+pub fn build_client(config: &Config, config_name: &str) -> Result<Client, String> {
+    let http = http_config(config, config_name);
+
+    let mut builder = Client::builder()
+        .user_agent(user_agent(config, config_name))
+        .gzip(http.gzip.unwrap_or(true));
+    if let Some(seconds) = http.connect_timeout {
+        builder = builder.connect_timeout(Duration::from_secs(seconds));
+    }
+    if let Some(seconds) = http.read_timeout {
+        builder = builder.timeout(Duration::from_secs(seconds));
+    }
+    if let Some(proxy) = &http.proxy {
+        builder = builder.proxy(Proxy::all(proxy).map_err(|e| format!("Invalid proxy '{}': {}", proxy, e))?);
+    }
+    if let Some(path) = &http.ca_bundle {
+        let pem = fs::read(path).map_err(|e| format!("Error reading CA bundle '{}': {}", path, e))?;
+        let certificates = Certificate::from_pem_bundle(&pem).map_err(|e| format!("Invalid CA bundle '{}': {}", path, e))?;
+        if certificates.is_empty() {
+            return Err(format!("No certificates found in CA bundle '{}'", path));
+        }
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    builder.build().map_err(|e| format!("Error creating HTTP client: {}", e))
+}
+
+// Shows the API token's last four characters at most, so requests can be shared.
+fn mask_token(token: &str) -> String {
+    let length = token.chars().count();
+    let hidden = if length > 8 { length - 4 } else { length };
+    format!("****{}", token.chars().skip(hidden).collect::<String>())
+}
+
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+// Prints `request` for --dry-run, or as the equivalent curl or HTTPie command for --curl and
+// --httpie. The token is masked in all three.
+pub fn format_request(request: &Request, api_token: &str, user_agent: &str, style: &str) -> String {
+    let url = request.url().as_str();
+    let headers = [("requestToken", mask_token(api_token)), ("User-Agent", user_agent.to_string())];
+    match style {
+        "--curl" => {
+            let headers: Vec<String> = headers.iter().map(|(name, value)| format!("-H {}", shell_quote(&format!("{}: {}", name, value)))).collect();
+            format!("curl {} {}", headers.join(" "), shell_quote(url))
+        }
+        "--httpie" => {
+            let headers: Vec<String> = headers.iter().map(|(name, value)| shell_quote(&format!("{}:{}", name, value))).collect();
+            format!("http GET {} {}", shell_quote(url), headers.join(" "))
+        }
+        _ => {
+            let headers: Vec<String> = headers.iter().map(|(name, value)| format!("{}: {}", name, value)).collect();
+            format!("{} {}\n{}", request.method(), url, headers.join("\n"))
+        }
+    }
+}
@@ -1,17 +1,64 @@
 use std::env;
+use std::fs;
+use std::io::{self, BufReader, Read};
+use std::ops::ControlFlow;
 use std::process;
-use serde::de::Error as SerdeDeError;
-use reqwest::blocking::Client;
-use crate::pretty::{prettify_json, prettify_yaml, fancy_status, boxed_message};
-use crate::args::{split_args, build_url, parse_filters, get_nested_value, extract_only_clause, extract_for_clause, replace_keywords_in_args};
+use std::time::Duration;
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::StatusCode;
+use chrono_tz::Tz;
+use crate::pretty::{prettify_json, prettify_yaml, write_ndjson, write_ndjson_stream, fancy_status, boxed_message, colorize_diff, configure_terminal};
+use crate::args::{split_args, build_url, parse_filters, get_nested_value, extract_for_clause, extract_option};
 use crate::config::{Config, EnvironmentConfig};
+use crate::watch::extract_watch_clause;
+use crate::diff::{extract_diff_clause, load_snapshot, compare, redact_diff, format_diff, DiffTarget};
+use crate::cache::ResponseCache;
+use crate::highlight::set_theme;
+use crate::redact::{configure_redaction, redact_field, redact_records};
+use crate::records::{parse_records, stream_records, filter_records, sort_records, apply_paging, is_local_filter};
+use crate::export::{extract_export_clause, write_export, Export};
+use crate::aliases::Aliases;
+use crate::http::{build_client, format_request, user_agent};
+use crate::retry::{configure_retries, send_with_retries};
+use crate::decode::{configure_decoding, decode_record, decode_records, Decoder};
+use crate::path::{is_simple_field, project};
+use crate::window::{extract_window_clause, Window, WindowMode};
+use crate::dates::parse_timezone;
 
 mod pretty;
 mod args;
+mod dates;
 mod config;
+mod watch;
+mod diff;
+mod cache;
+mod records;
+mod export;
+mod highlight;
+mod redact;
+mod window;
+mod aliases;
+mod path;
+mod decode;
+mod retry;
+mod http;
+
+type QueryParams = Vec<(String, String)>;
+
+struct QueryRequest<'a> {
+    environment: &'a str,
+    url: String,
+    api_token: &'a str,
+    query_params: QueryParams,
+    // Sent instead when the API rejects a server-side sort, so records are sorted locally.
+    fallback_params: Option<QueryParams>,
+    // Filters the API can't evaluate, applied to the records once they are fetched.
+    local_filters: QueryParams,
+}
 
 fn main() {
     let args: Vec<String> = env::args().skip(1).collect();
+    configure_terminal(&args).unwrap_or_else(|e| handle_error(e));
     if args.contains(&"help".to_string()) {
         print_help();
         return;
@@ -24,38 +71,173 @@ fn main() {
         Config::create_new_config();
         return;
     }
-    let replaced_args_string = replace_keywords_in_args(&args);
-    let replaced_args: Vec<String> = replaced_args_string.split_whitespace().map(String::from).collect();
-    let (format, quiet) = parse_args(&replaced_args);
-    let config_name = extract_for_clause(&replaced_args);
-    let only_records = extract_only_clause(&replaced_args);
+    if args.first().is_some_and(|arg| arg == "cache") {
+        clear_cache(&args);
+        return;
+    }
+    let (args, key) = extract_option(&args, "--key").unwrap_or_else(|e| handle_error(e));
+    let (args, from_file) = extract_option(&args, "--from-file").unwrap_or_else(|e| handle_error(e));
+    let (args, watch_options) = extract_watch_clause(&args).unwrap_or_else(|e| handle_error(e));
+    let (args, diff_target) = extract_diff_clause(&args).unwrap_or_else(|e| handle_error(e));
+    let (args, export) = extract_export_clause(&args).unwrap_or_else(|e| handle_error(e));
+    if export.is_some() && (watch_options.is_some() || diff_target.is_some()) {
+        handle_error("'into' and --out can't be used with watch or diff".to_string());
+    }
+    let (args, tz_name) = extract_option(&args, "--tz").unwrap_or_else(|e| handle_error(e));
+    let (args, retries) = extract_option(&args, "--retries").unwrap_or_else(|e| handle_error(e));
+    let (args, rate_limit) = extract_option(&args, "--rate-limit").unwrap_or_else(|e| handle_error(e));
+    let tz = resolve_timezone(tz_name, &args).unwrap_or_else(|e| handle_error(e));
+    // Clauses may also be passed as one quoted argument, e.g. `where 'status eq 1, method eq card'`.
+    let args: Vec<String> = args.join(" ").split_whitespace().map(String::from).collect();
+    let (args, window) = extract_window_clause(&args).unwrap_or_else(|e| handle_error(e));
+    let (format, quiet) = parse_args(&args);
+    let config_name = extract_for_clause(&args);
+
+    if let Some(path) = from_file {
+        if !quiet {
+            eprintln!("{}", boxed_message(Some("📂"), &format!("File: {}", path)));
+        }
+        let config = Config::load_existing();
+        if let Some(theme) = config.as_ref().and_then(|config| config.theme.as_ref()) {
+            set_theme(theme).unwrap_or_else(|e| handle_error(e));
+        }
+        let env_config = config.as_ref().and_then(|config| config.environments.get(&config_name));
+        configure_redaction(&args, env_config).unwrap_or_else(|e| handle_error(e));
+        let text = read_input(&path).unwrap_or_else(|e| handle_error(e));
+        let (route_parts, filter_args, sort_clause) = split_args(&args);
+        let endpoint = route_parts.iter().find(|part| !part.starts_with("--")).map(String::as_str);
+        let aliases = Aliases::load(endpoint, config.as_ref());
+        let decoder = Decoder::load(endpoint, config.as_ref());
+        // Every filter is evaluated locally, on the record fields the API filter names stand for.
+        let filters = resolve_filters(&filter_args, &aliases, &decoder, tz).unwrap_or_else(|e| handle_error(format!("Error parsing filters: {}", e)));
+        let filters = decoder.record_filters(filters);
+        let sort_clause = sort_clause.map(|(field, order)| (aliases.resolve(&field), order));
+        configure_decoding(&args, decoder);
+        let records = parse_records(&text)
+            .and_then(|records| filter_records(records, &filters))
+            .unwrap_or_else(|e| handle_error(format!("Error processing '{}': {}", path, e)));
+        output_records(records, &window, &sort_clause, format, &args, export.as_ref());
+        return;
+    }
+
     let config = Config::load();
-    let env_config = config.environments.get(&config_name).unwrap_or_else(|| {
-        handle_error(format!("Configuration '{}' not found. Please provide a valid configuration name.", config_name))
-    });
-    let api_token = &env_config.api_token;
-    let base_url = get_base_url(&env_config.environment);
-    let (route_parts, filter_args, sort_clause) = process_route_args(&replaced_args, &env_config);
-    let url = build_url(&base_url, &route_parts);
-    let query_params = parse_filters(&filter_args).unwrap_or_else(|e| handle_error(format!("Error parsing filters: {}", e)));
+    if let Some(theme) = &config.theme {
+        set_theme(theme).unwrap_or_else(|e| handle_error(e));
+    }
+    let (request, sort_clause, window) = prepare_request(&args, &config, &config_name, &window, tz);
+    configure_redaction(&args, config.environments.get(&config_name)).unwrap_or_else(|e| handle_error(e));
+    let cache = ResponseCache::from_args(&args, config.cache_ttl);
+    configure_retries(retries, rate_limit, config.retry.as_ref()).unwrap_or_else(|e| handle_error(e));
+
+    let client = build_client(&config, &config_name).unwrap_or_else(|e| handle_error(e));
+
+    if let Some(style) = ["--dry-run", "--curl", "--httpie"].into_iter().find(|flag| args.contains(&flag.to_string())) {
+        let built = build_request(&client, &request, &request.query_params).build().unwrap_or_else(|e| handle_error(format!("Invalid request: {}", e)));
+        println!("{}", format_request(&built, request.api_token, &user_agent(&config, &config_name), style));
+        if !quiet && !request.local_filters.is_empty() {
+            let filters: Vec<String> = request.local_filters.iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+            eprintln!("Note: {} will be applied locally to the response", filters.join(", "));
+        }
+        return;
+    }
+
+    if let Some(target) = diff_target {
+        let current = fetch_records(&client, &request, cache.as_ref()).unwrap_or_else(|e| handle_error(e));
+        let (baseline_name, baseline) = match target {
+            DiffTarget::Config(name) => {
+                let (baseline_request, _, _) = prepare_request(&args, &config, &name, &window, tz);
+                let baseline_client = build_client(&config, &name).unwrap_or_else(|e| handle_error(e));
+                (name, fetch_records(&baseline_client, &baseline_request, cache.as_ref()))
+            }
+            DiffTarget::Snapshot(path) => (path.display().to_string(), load_snapshot(&path)),
+        };
+        let baseline = baseline.unwrap_or_else(|e| handle_error(e));
+        let diff = compare(&baseline, &current, key.as_deref());
+        let diff = redact_diff(diff, &baseline, &current, key.as_deref());
+        if !quiet {
+            eprintln!("{}", boxed_message(Some("🔧"), &format!("Config: {} against {}", config_name, baseline_name)));
+            eprintln!("{}", boxed_message(Some("🔍"), &format!("{} added, {} removed, {} changed", diff.added.len(), diff.removed.len(), diff.changed.len())));
+        }
+        let text = format_diff(&diff);
+        if !text.is_empty() {
+            println!("{}", colorize_diff(&text));
+        }
+        return;
+    }
+
+    if let Some(watch_options) = watch_options {
+        if !quiet {
+            eprintln!("{}", boxed_message(Some("🔧"), &format!("Config: {}", config_name)));
+            eprintln!("{}", boxed_message(Some("👀"), &request.url));
+        }
+        watch::run(
+            &watch_options,
+            key.as_deref(),
+            || fetch_records(&client, &request, None),
+            |records| output_records(records, &window, &sort_clause, format, &args, None),
+        );
+    }
 
-    let client = Client::new();
-    let result = client.get(&url)
-        .header("requestToken", api_token)
-        .query(&query_params)
-        .send();
+    // With nothing to sort, sample, filter or export, `--ndjson` writes the records as they are
+    // read from the response, so memory use doesn't grow with the number of records.
+    let streams = format == "--ndjson" && sort_clause.is_none() && window.mode == WindowMode::First
+        && request.local_filters.is_empty() && export.is_none() && cache.is_none();
+    let result = if streams {
+        send_with_retries(|| build_request(&client, &request, &request.query_params).send(), |response| Ok(Body::Stream(response)))
+            .map(|(status, body)| (status, body, None))
+    } else {
+        fetch_response(&client, &request, cache.as_ref()).map(|(status, text, age)| (status, Body::Text(text), age))
+    };
 
     if !quiet {
-        println!("{}", boxed_message(Some("🔧"), &format!("Config: {}", config_name)));
-        println!("{}", boxed_message(Some("🚀"), &url));
+        eprintln!("{}", boxed_message(Some("🔧"), &format!("Config: {}", config_name)));
+        eprintln!("{}", boxed_message(Some("🚀"), &request.url));
     }
 
     match result {
-        Ok(resp) => handle_response(resp, only_records, sort_clause, format, &args, quiet),
-        Err(e) => eprintln!("Request failed: {}", e),
+        Ok((status, body, age)) => {
+            if !quiet {
+                let cached = age.map_or(String::new(), |age| format!(" (cached {}s ago)", age.as_secs()));
+                eprintln!("{}", fancy_status(&format!("Status: {}{}", status, cached)));
+            }
+            let text = match body {
+                Body::Stream(response) if status.is_success() => {
+                    stream_ndjson(response, &window).unwrap_or_else(|e| handle_error(format!("Error parsing response: {}", e)));
+                    return;
+                }
+                Body::Stream(response) => response.text().unwrap_or_else(|e| handle_error(format!("Error reading response: {}", e))),
+                Body::Text(text) => text,
+            };
+            process_text(&text, &request.local_filters, &window, sort_clause, format, &args, export.as_ref());
+        }
+        Err(e) => eprintln!("{}", e),
     }
 }
 
+// A response body, read in full or left to be streamed.
+enum Body {
+    Text(String),
+    Stream(Response),
+}
+
+// Writes the records of `response` as NDJSON while it is being read. The window's skip and count
+// are applied on the way, and reading stops once the count is reached.
+fn stream_ndjson(response: Response, window: &Window) -> Result<(), String> {
+    let mut skip = window.skip;
+    let mut count = window.count.unwrap_or(usize::MAX);
+    write_ndjson_stream(|write| stream_records(BufReader::new(response), |record| {
+        if skip > 0 {
+            skip -= 1;
+            return ControlFlow::Continue(());
+        }
+        if count == 0 {
+            return ControlFlow::Break(());
+        }
+        count -= 1;
+        write(decode_record(redact_field("", record)))
+    }))
+}
+
 fn print_help() {
     println!(
         "payquery - A command-line interface for calling Payabli's Query APIs.\n\
@@ -67,40 +249,149 @@ fn print_help() {
           new                   Create a new configuration.\n\
           list                  List all available configurations.\n\
           help                  Show this help message.\n\
+          watch INTERVAL ...    Re-run the query every INTERVAL and print only new or changed records.\n\
+          (INTERVAL is a number of seconds, or uses an s, m or h suffix)\n\
+          diff ... against X    Compare the query results with configuration X or a saved JSON snapshot X.\n\
+          (prints added (+), removed (-) and changed (~) records)\n\
+          cache clear           Delete all cached responses.\n\
         \n\
         OPTIONS:\n\
           --json                Output in JSON format (default).\n\
           --yaml                Output in YAML format.\n\
+          --ndjson              Output one compact JSON record per line.\n\
+          (written as the response arrives, unless sorted, filtered locally, cached, exported or windowed with last or sample)\n\
           --quiet               Don't output information besides the query result.\n\
+          --color=WHEN          Color the output: auto (default), always or never.\n\
+          (auto colors only terminals and honors NO_COLOR; banners are written to stderr)\n\
+          --ascii               Draw banners without box-drawing characters or emoji.\n\
+          --redact              Mask names, emails, phone numbers, addresses and card or bank data.\n\
+          --decode              Show status codes with their labels, e.g. TransStatus: 1 (Approved).\n\
+          --retries N           Retry a failed request up to N times (default 3, 0 to disable).\n\
+          (connection errors, timeouts, 429 and 5xx responses are retried with growing, randomized delays\n\
+          and Retry-After is honored up to max_delay_ms)\n\
+          --rate-limit N        Send at most N requests per second.\n\
+          --dry-run             Print the request (the URL with its query string, and headers) instead of sending it.\n\
+          --curl, --httpie      Print the request as a curl or HTTPie command instead of sending it.\n\
+          (the API token is masked; filters applied locally to the response are listed on stderr)\n\
+          --tz ZONE             Read dates like 'today' or 'Oct 15 @ 14:30' in ZONE (e.g. America/New_York).\n\
+          --cache               Cache responses for 'cache_ttl' seconds (default 60), readable only by you.\n\
+          --no-cache            Neither read nor write the response cache, even if 'cache_ttl' is set.\n\
+          --refresh             Ignore cached responses, but cache the new one.\n\
+          --from-file PATH      Run the clauses against a saved JSON response instead of the API.\n\
+          (use '-' as PATH to read from stdin; WHERE filters are then evaluated locally)\n\
+          --out FILE            Write the result to FILE instead of the terminal.\n\
+          (the format is picked from the extension: .json, .ndjson, .yaml or .csv)\n\
+          --force               Overwrite the file given to --out or INTO.\n\
+          --bell                Ring the terminal bell when a watch finds changes.\n\
+          --hook CMD            Run CMD when a watch finds changes (records are passed on stdin).\n\
+          --key FIELD           Identify records by FIELD when watching or diffing (default: the record ID).\n\
         \n\
         CLAUSES:\n\
-          only N ...            Limit the number of records to N.\n\
-          (comes before the API endpoint)\n\
+          only N, first N       Keep the first N records (after sorting).\n\
+          last N                Keep the last N records (after sorting).\n\
+          sample N              Keep N records picked at random.\n\
+          skip N                Drop the first N records, before any of the above.\n\
+          page P of size S      Keep the P-th group of S records, e.g. page 3 of size 100 is records 201 to 300.\n\
+          (the API does the sorting and paging where it can)\n\
           ... for NAME          Use the configuration named NAME.\n\
           ... where FILTERS     FILTER records based on the given conditions.\n\
           (https://docs.payabli.com/developer-guides/reporting-filters-and-conditions-reference)\n\
           ... by FIELD          Sort records by FIELD in ascending order.\n\
           ... by FIELD desc     Sort records by FIELD in descending order.\n\
+          (numbers are compared by value; records without FIELD come last)\n\
+          (field names ignore case, and aliases such as amount, date or id name each endpoint's usual fields)\n\
+          (fields can be paths: Customer.Email, Events[0].EventTime, Events[-1], Events[*].TransEvent or ..Email)\n\
           ... crop              Output only the sorted field values.\n\
           (must come after a BY clause)\n\
+          ... into FILE         Same as --out FILE.\n\
+        \n\
+        FILTERS:\n\
+          FIELD OP VALUE        Compare FIELD using eq, ne, gt, ge, lt, le, ct, nct, in or nin (or =, !=, >, >=, <, <=).\n\
+          FIELD VALUE           Same as FIELD eq VALUE.\n\
+          FIELD in (A, B, ...)  FIELD is one of the listed values; not_in (or nin) excludes them.\n\
+          (also [A, B], A|B, or @FILE with one value per line; @- reads the values from stdin)\n\
+          FIELD between A and B  FIELD is at least A and less than B (e.g. TransactionDate between Oct 1 and Oct 15).\n\
+          FIELD during PERIOD   FIELD falls within PERIOD: a period keyword, a day, a month name or a year\n\
+          (e.g. TransactionDate during last week, during yesterday, during October, during 2025)\n\
+          FIELD is [not] null   FIELD is missing or null (or has a value).\n\
+          FIELD exists          FIELD is present in the record, even if it is null.\n\
+          not FILTER            Inverts FILTER, e.g. not status eq 1 or not TransactionDate during last week.\n\
+          (null checks and inverted ranges are evaluated locally, after fetching every matching record)\n\
+          Status fields also take labels instead of codes, e.g. status eq approved or status in (hold, voided).\n\
+          Separate several filters with commas. Dates (see below) are only read in filter values;\n\
+          (quote a value to keep it literal, e.g. where 'Name eq \"Today\", Description ct \"March 3\"')\n\
+        \n\
+        DATES:\n\
+          now, today, yesterday, tomorrow\n\
+          this/next/last week, month, quarter or year  The first moment of that period.\n\
+          start of PERIOD       The first moment of PERIOD (e.g. start of quarter).\n\
+          end of PERIOD         The last moment of PERIOD (e.g. end of last month, end of week).\n\
+          N UNITS ago, in N UNITS  Relative to now (e.g. 3 days ago, in 2 weeks, 6 hours ago).\n\
+          -2w, today-7d, now+1d-2h  Offsets in s, m (or min), h, d, w, mo or y, from now or a day keyword.\n\
+          [last|next] friday    Midnight on that weekday of this week, or the previous/next one.\n\
+          2024-10-15[T14:30[:00]]  An ISO date with an optional time.\n\
+          Oct 15 [2024] [@ 14:30]  A calendar date with an optional year and time.\n\
         \n\
         EXAMPLES:\n\
           payquery new\n\
           payquery list\n\
           payquery only 5 transactions\n\
           payquery chargebacks where method eq card\n\
+          payquery transactions where TransactionDate ge last month, TransactionDate le end of last month\n\
+          payquery transactions where TransactionDate ge 10 days ago\n\
+          payquery transactions where TransactionDate during last month, TotalAmount between 100 and 500\n\
           payquery batches for ISV_Pizzabli by TransactionDate\n\
+          payquery only 10 transactions by NetAmount desc\n\
+          payquery page 3 of size 100 transactions where BatchNumber eq 1234\n\
+          payquery transactions where 'status in (1, 2, 5)'\n\
+          payquery --decode transactions where status eq approved\n\
+          payquery --curl transactions where TransactionDate during yesterday\n\
+          payquery transactions where PaymentTransId in @ids.txt\n\
+          payquery transactions where SettlementDate is null, TransactionDate during last week\n\
+          payquery transactions where 'Events[*].TransEvent eq Refund' by 'Events[-1].EventTime' crop\n\
+          payquery watch 30s transactions where status eq 1 --bell\n\
+          payquery diff customers for sandbox against qa --key customerNumber\n\
+          payquery --from-file export.json where TotalAmount gt 100 by TotalAmount desc\n\
+          payquery settlements for ISV_Pizzabli into settlements.csv\n\
           payquery only 10 customers for ISV_Pizzabli where firstname eq John by Lastname crop\n\
         \n\
         CONFIGURATION:\n\
           Configurations are stored in a YAML file located in your home directory as 'payquery.yml'.\n\
           Each configuration contains API token, organization ID, entrypoint, and environment.\n\
-          Use the 'new' subcommand to create or update configurations.\n"
+          Use the 'new' subcommand to create or update configurations.\n\
+          Responses are only cached with --cache, or when 'cache_ttl' is set (in seconds, 0 to disable).\n\
+          Output colors can be changed with a 'theme' section, e.g. 'theme: {{ key: cyan, null: bright red }}'\n\
+          (keys: key, string, number, boolean, null, punctuation).\n\
+          Set 'timezone' on a configuration to read its dates in that zone instead of UTC.\n\
+          Set 'redact: true' on a configuration to always mask its output, and list extra fields to mask\n\
+          in 'redact_fields' as dotted paths (Customer.Email) or /regular expressions/ on those paths.\n\
+          Retries and the rate limit can also be set in a 'retry' section,\n\
+          e.g. 'retry: {{ retries: 5, base_delay_ms: 500, max_delay_ms: 30000, rate_limit: 2 }}'.\n\
+          The HTTP client is set up in an 'http' section, globally or on a configuration: connect_timeout and\n\
+          read_timeout (in seconds), proxy, ca_bundle (a PEM file of extra root certificates), gzip and user_agent,\n\
+          e.g. 'http: {{ proxy: http://proxy.corp:8080, ca_bundle: /etc/ssl/corp-ca.pem }}'.\n\
+          Field names can be shortened with an 'aliases' section, for every endpoint or for one endpoint,\n\
+          e.g. 'aliases: {{ who: Customer.Lastname, transactions: {{ amount: NetAmount }} }}'.\n\
+          --decode labels and label filters can be extended with a 'decode' section, per endpoint,\n\
+          e.g. 'decode: {{ transactions: {{ TransStatus: {{ 9: Disputed }} }} }}'.\n"
     );
 }
 
+fn clear_cache(args: &[String]) {
+    if args.get(1).is_none_or(|arg| arg != "clear") {
+        handle_error("Usage: payquery cache clear".to_string());
+    }
+    match ResponseCache::clear() {
+        Ok(removed) => println!("Removed {} cached responses.", removed),
+        Err(e) => handle_error(format!("Error clearing cache: {}", e)),
+    }
+}
+
 fn list_configs() {
     let config = Config::load();
+    if let Some(theme) = &config.theme {
+        set_theme(theme).unwrap_or_else(|e| handle_error(e));
+    }
     println!("Available configurations:");
     for (name, _) in config.environments {
         println!("  - {}", name);
@@ -117,6 +408,9 @@ fn parse_args(args: &[String]) -> (&str, bool) {
             "--yaml" => {
                 format = "--yaml";
             }
+            "--ndjson" => {
+                format = "--ndjson";
+            }
             "--quiet" => {
                 quiet = true;
             }
@@ -137,15 +431,95 @@ fn get_base_url(environment: &str) -> &'static str {
 
 
 
+// Date keywords are read in the --tz zone, else the configuration's 'timezone', else UTC.
+fn resolve_timezone(tz_name: Option<String>, args: &[String]) -> Result<Tz, String> {
+    let tz_name = tz_name.or_else(|| {
+        Config::load_existing()?.environments.remove(&extract_for_clause(args))?.timezone
+    });
+    tz_name.map_or(Ok(Tz::UTC), |name| parse_timezone(&name))
+}
+
+// Parses the filters and resolves field aliases and status labels in them, the same way for the
+// API and for --from-file.
+fn resolve_filters(filter_args: &[String], aliases: &Aliases, decoder: &Decoder, tz: Tz) -> Result<QueryParams, String> {
+    let filters = aliases.resolve_filters(parse_filters(filter_args, tz)?);
+    decoder.encode_filters(filters)
+}
+
+// Also returns the sort clause and the part of the window that is left to apply locally.
+fn prepare_request<'a>(args: &[String], config: &'a Config, config_name: &str, window: &Window, tz: Tz) -> (QueryRequest<'a>, Option<(String, String)>, Window) {
+    let env_config = config.environments.get(config_name).unwrap_or_else(|| {
+        handle_error(format!("Configuration '{}' not found. Please provide a valid configuration name.", config_name))
+    });
+    let base_url = get_base_url(&env_config.environment);
+    let (route_parts, filter_args, sort_clause) = process_route_args(args, env_config);
+    let url = build_url(base_url, &route_parts);
+    let endpoint = route_parts.first().map(String::as_str);
+    let aliases = Aliases::load(endpoint, Some(config));
+    let decoder = Decoder::load(endpoint, Some(config));
+    let filters = resolve_filters(&filter_args, &aliases, &decoder, tz).unwrap_or_else(|e| handle_error(format!("Error parsing filters: {}", e)));
+    let (local_filters, filters): (QueryParams, QueryParams) = filters.into_iter().partition(|(key, _)| is_local_filter(key));
+    let local_filters = decoder.record_filters(local_filters);
+    let sort_clause = sort_clause.map(|(field, order)| (aliases.resolve(&field), order));
+    configure_decoding(args, decoder);
+    let (paging, local_window) = paging_params(&sort_clause, window, !local_filters.is_empty());
+    let fallback_params = paging.iter().any(|(name, _)| name == "sortBy")
+        .then(|| filters.iter().cloned().chain([("limitRecord".to_string(), "0".to_string())]).collect());
+    let query_params = filters.into_iter().chain(paging).collect();
+
+    let request = QueryRequest { environment: &env_config.environment, url, api_token: &env_config.api_token, query_params, fallback_params, local_filters };
+    (request, sort_clause, local_window)
+}
+
+// Translates `by` and the window into `sortBy`, `fromRecord` and `limitRecord`, so the API only
+// returns the records that are needed. Nested fields and paths can't be sorted by the API, filters it
+// can't evaluate change which records are kept, and `last` or `sample` need every record, so
+// those fetch everything (`limitRecord=0`) and finish locally.
+fn paging_params(sort_clause: &Option<(String, String)>, window: &Window, local_filters: bool) -> (QueryParams, Window) {
+    let param = |name: &str, value: usize| (name.to_string(), value.to_string());
+    let server_sort = sort_clause.as_ref().filter(|(field, _)| is_simple_field(field));
+    if local_filters || (sort_clause.is_some() && server_sort.is_none()) {
+        return (vec![param("limitRecord", 0)], window.clone());
+    }
+
+    // The last N records in one order are the first N in the other.
+    let reverse = window.mode == WindowMode::Last && window.count.is_some() && server_sort.is_some() && window.skip == 0;
+    let limit = window.count.map(|count| if window.mode == WindowMode::First || reverse { count } else { 0 });
+
+    let mut params = Vec::new();
+    if let Some((field, order)) = server_sort {
+        let descending = (order == "desc") != reverse;
+        params.push(("sortBy".to_string(), format!("{}({})", if descending { "desc" } else { "asc" }, field)));
+    }
+    if window.skip > 0 {
+        params.push(param("fromRecord", window.skip));
+    }
+    if let Some(limit) = limit {
+        params.push(param("limitRecord", limit));
+    }
+    (params, Window { skip: 0, ..window.clone() })
+}
+
+// Reads a saved response from PATH, or from stdin when PATH is '-'.
+fn read_input(path: &str) -> Result<String, String> {
+    if path == "-" {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text).map_err(|e| format!("Error reading stdin: {}", e))?;
+        Ok(text)
+    } else {
+        fs::read_to_string(path).map_err(|e| format!("Error reading '{}': {}", path, e))
+    }
+}
+
 fn process_route_args(args: &[String], env_config: &EnvironmentConfig) -> (Vec<String>, Vec<String>, Option<(String, String)>) {
-    let start_index = args.iter().position(|arg| !arg.starts_with("--") && !arg.parse::<usize>().is_ok()).unwrap_or(0);
+    let start_index = args.iter().position(|arg| !arg.starts_with("--") && arg.parse::<usize>().is_err()).unwrap_or(0);
     let (mut route_parts, filter_args, sort_clause) = split_args(&args[start_index..]);
 
     if let Some(last_param) = route_parts.last() {
         if last_param == "org" {
             let org_id = env_config.org_id.clone();
             route_parts.push(org_id);
-        } else if !last_param.chars().any(|c| c.is_digit(10)) {
+        } else if !last_param.chars().any(|c| c.is_ascii_digit()) {
             let entrypoint = env_config.entrypoint.clone();
             route_parts.push(entrypoint);
         }
@@ -154,26 +528,99 @@ fn process_route_args(args: &[String], env_config: &EnvironmentConfig) -> (Vec<S
     (route_parts, filter_args, sort_clause)
 }
 
-fn handle_response(resp: reqwest::blocking::Response, only_records: Option<usize>, sort_clause: Option<(String, String)>, format: &str, args: &[String], quiet: bool) {
-    if !quiet {
-        println!("{}", fancy_status(&format!("Status: {}", resp.status())));
+fn process_text(text: &str, local_filters: &[(String, String)], window: &Window, sort_clause: Option<(String, String)>, format: &str, args: &[String], export: Option<&Export>) {
+    let records = parse_records(text)
+        .and_then(|records| filter_records(records, local_filters))
+        .unwrap_or_else(|e| handle_error(format!("Error parsing response: {}", e)));
+    output_records(records, window, &sort_clause, format, args, export);
+}
+
+// Returns the status, the body and, when served from the cache, the age of the cached body.
+fn fetch_response(client: &Client, request: &QueryRequest, cache: Option<&ResponseCache>) -> Result<(StatusCode, String, Option<Duration>), String> {
+    let cache_key = ResponseCache::key(request.environment, request.api_token, &request.url, &request.query_params);
+    if let Some((text, age)) = cache.and_then(|cache| cache.read(&cache_key)) {
+        return Ok((StatusCode::OK, text, Some(age)));
+    }
+
+    let (mut status, mut text) = send_request(client, request, &request.query_params)?;
+    if status == StatusCode::BAD_REQUEST
+        && let Some(fallback_params) = &request.fallback_params
+        && rejects_sort(&text, &request.query_params) {
+        eprintln!("Warning: the API could not sort this query, sorting all records locally instead");
+        (status, text) = send_request(client, request, fallback_params)?;
+        // Sorts and pages the full result the way the API would have, so the rest of the
+        // pipeline sees the same response either way.
+        if status.is_success() {
+            let records = parse_records(&text).map_err(|e| format!("Error parsing response: {}", e))?;
+            text = serde_json::to_string(&apply_paging(records, &request.query_params)).map_err(|e| e.to_string())?;
+        }
     }
-    match resp.text() {
-        Ok(text) => process_text(&text, only_records, sort_clause, format, args),
-        Err(e) => eprintln!("Error reading response: {}", e),
+    if status.is_success()
+        && let Some(cache) = cache
+        && let Err(e) = cache.write(&cache_key, &text) {
+        eprintln!("Warning: could not write response cache: {}", e);
     }
+    Ok((status, text, None))
 }
 
-fn process_text(text: &str, only_records: Option<usize>, sort_clause: Option<(String, String)>, format: &str, args: &[String]) {
-    let records: Vec<serde_json::Value> = serde_json::from_str(text)
-        .and_then(|v: serde_json::Value| v["Records"].as_array().cloned().ok_or_else(|| "Invalid response format".to_string()).map_err(|e| SerdeDeError::custom(e)))
-        .unwrap_or_else(|e| handle_error(format!("Error parsing response: {}", e)));
+fn build_request(client: &Client, request: &QueryRequest, query_params: &[(String, String)]) -> RequestBuilder {
+    client.get(&request.url)
+        .header("requestToken", request.api_token)
+        .query(query_params)
+}
+
+// Whether a 400 response is about the sort rather than, say, a filter: its body mentions
+// sorting or the field being sorted by.
+fn rejects_sort(body: &str, query_params: &[(String, String)]) -> bool {
+    let body = body.to_lowercase();
+    let sort_field = query_params.iter()
+        .find(|(name, _)| name == "sortBy")
+        .and_then(|(_, value)| value.split_once('('))
+        .map(|(_, field)| field.trim_end_matches(')').to_lowercase());
+    body.contains("sort") || sort_field.is_some_and(|field| body.contains(&field))
+}
+
+fn send_request(client: &Client, request: &QueryRequest, query_params: &[(String, String)]) -> Result<(StatusCode, String), String> {
+    send_with_retries(|| build_request(client, request, query_params).send(), Response::text)
+}
+
+fn fetch_records(client: &Client, request: &QueryRequest, cache: Option<&ResponseCache>) -> Result<Vec<serde_json::Value>, String> {
+    let (status, text, _) = fetch_response(client, request, cache)?;
+    if !status.is_success() {
+        return Err(format!("Request failed with status {}", status));
+    }
+    parse_records(&text)
+        .and_then(|records| filter_records(records, &request.local_filters))
+        .map_err(|e| format!("Error parsing response: {}", e))
+}
 
-    let records = only_records.map_or(records.clone(), |limit| records.iter().take(limit).cloned().collect());
-    let records = sort_clause.as_ref().map_or(records.clone(), |(key, order)| sort_records(records.clone(), key, order));
+fn output_records(records: Vec<serde_json::Value>, window: &Window, sort_clause: &Option<(String, String)>, format: &str, args: &[String], export: Option<&Export>) {
+    let records = match sort_clause {
+        Some((key, order)) => sort_records(records, key, order),
+        None => records,
+    };
+    let records = decode_records(redact_records(window.apply(records)));
+    let crop = sort_clause.as_ref().is_some_and(|(_, _)| args.contains(&"crop".to_string()));
+    if let Some((field, _)) = sort_clause
+        && !records.is_empty()
+        && records.iter().all(|record| get_nested_value(record, field).is_none()) {
+        eprintln!("Warning: no record has a field '{}' to sort{} by", field, if crop { " or crop" } else { "" });
+    }
 
-    if sort_clause.as_ref().map_or(false, |(_, _)| args.contains(&"crop".to_string())) {
-        records.iter().filter_map(|record| get_nested_value(record, &sort_clause.as_ref().unwrap().0)).for_each(|value| println!("{}", value));
+    if let Some(export) = export {
+        let records: Vec<serde_json::Value> = if crop {
+            records.iter().filter_map(|record| project(record, &sort_clause.as_ref().unwrap().0)).collect()
+        } else {
+            records
+        };
+        write_export(export, &records).unwrap_or_else(|e| handle_error(e));
+        if !args.contains(&"--quiet".to_string()) {
+            eprintln!("{}", boxed_message(Some("💾"), &format!("Wrote {} records to {}", records.len(), export.path.display())));
+        }
+    } else if crop {
+        records.iter().filter_map(|record| project(record, &sort_clause.as_ref().unwrap().0)).for_each(|value| println!("{}", value));
+    } else if format == "--ndjson" {
+        write_ndjson(&records).unwrap_or_else(|e| handle_error(format!("Error writing records: {}", e)));
     } else {
         let sorted_text = serde_json::to_string(&records).unwrap_or_else(|e| handle_error(format!("Error serializing sorted records: {}", e)));
         match format {
@@ -184,21 +631,6 @@ fn process_text(text: &str, only_records: Option<usize>, sort_clause: Option<(St
     }
 }
 
-fn sort_records(records: Vec<serde_json::Value>, key: &str, order: &str) -> Vec<serde_json::Value> {
-    let mut records = records;
-    records.sort_by(|a, b| {
-        let default_value = serde_json::Value::default();
-        let a_value = get_nested_value(&a, key).unwrap_or(&default_value);
-        let b_value = get_nested_value(&b, key).unwrap_or(&default_value);
-        if order == "desc" {
-            b_value.to_string().cmp(&a_value.to_string())
-        } else {
-            a_value.to_string().cmp(&b_value.to_string())
-        }
-    });
-    records
-}
-
 fn handle_error(message: String) -> ! {
     eprintln!("{}", message);
     process::exit(1);
@@ -210,3 +642,69 @@ fn process_output(result: Result<String, String>) {
         Err(e) => eprintln!("Error processing output: {}", e),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(skip: usize, count: Option<usize>, mode: WindowMode) -> Window {
+        Window { skip, count, mode }
+    }
+
+    fn sort(field: &str, order: &str) -> Option<(String, String)> {
+        Some((field.to_string(), order.to_string()))
+    }
+
+    // The query parameters as `name=value`, and the skip and count left to apply locally.
+    fn paging(sort_clause: Option<(String, String)>, window: Window, local_filters: bool) -> (Vec<String>, usize, Option<usize>) {
+        let (params, local) = paging_params(&sort_clause, &window, local_filters);
+        let params = params.into_iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+        (params, local.skip, local.count)
+    }
+
+    #[test]
+    fn first_is_sent_as_a_limit() {
+        assert_eq!(paging(None, window(0, Some(10), WindowMode::First), false), (vec!["limitRecord=10".to_string()], 0, Some(10)));
+        assert_eq!(
+            paging(sort("TotalAmount", "desc"), window(0, Some(10), WindowMode::First), false),
+            (vec!["sortBy=desc(TotalAmount)".to_string(), "limitRecord=10".to_string()], 0, Some(10)),
+        );
+    }
+
+    #[test]
+    fn last_reverses_a_server_sort() {
+        assert_eq!(
+            paging(sort("TotalAmount", "asc"), window(0, Some(5), WindowMode::Last), false),
+            (vec!["sortBy=desc(TotalAmount)".to_string(), "limitRecord=5".to_string()], 0, Some(5)),
+        );
+        // Without a sort there is no other order to take the first records of.
+        assert_eq!(paging(None, window(0, Some(5), WindowMode::Last), false), (vec!["limitRecord=0".to_string()], 0, Some(5)));
+    }
+
+    #[test]
+    fn pages_are_sent_as_from_and_limit() {
+        assert_eq!(
+            paging(None, window(200, Some(100), WindowMode::First), false),
+            (vec!["fromRecord=200".to_string(), "limitRecord=100".to_string()], 0, Some(100)),
+        );
+        assert_eq!(
+            paging(sort("TransactionTime", "asc"), window(200, Some(100), WindowMode::First), false),
+            (vec!["sortBy=asc(TransactionTime)".to_string(), "fromRecord=200".to_string(), "limitRecord=100".to_string()], 0, Some(100)),
+        );
+    }
+
+    #[test]
+    fn local_sorts_and_filters_fetch_everything() {
+        let page = || window(200, Some(100), WindowMode::First);
+        assert_eq!(paging(sort("Customer.Lastname", "asc"), page(), false), (vec!["limitRecord=0".to_string()], 200, Some(100)));
+        assert_eq!(paging(sort("TotalAmount", "asc"), page(), true), (vec!["limitRecord=0".to_string()], 200, Some(100)));
+    }
+
+    #[test]
+    fn only_sort_errors_fall_back_to_local_sorting() {
+        let params = vec![("sortBy".to_string(), "desc(TotalAmount)".to_string())];
+        assert!(rejects_sort(r#"{"responseText": "Invalid sortBy parameter"}"#, &params));
+        assert!(rejects_sort(r#"{"responseText": "Unknown field totalamount"}"#, &params));
+        assert!(!rejects_sort(r#"{"responseText": "Invalid value for status(eq)"}"#, &params));
+    }
+}
@@ -0,0 +1,120 @@
+use serde_json::Value;
+
+// One step of a field path such as `Events[-1].TransEvent`, `Events[*].TransEvent` or `..Email`.
+enum Step {
+    Key(String),
+    Index(i64),
+    Wildcard,
+    Descend(String),
+}
+
+fn parse_path(path: &str) -> Result<Vec<Step>, String> {
+    let path = path.strip_prefix('$').unwrap_or(path);
+    let mut steps = Vec::new();
+    let mut rest = path;
+
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("..") {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            if end == 0 {
+                return Err(format!("Missing field name after '..' in '{}'", path));
+            }
+            steps.push(Step::Descend(after[..end].to_string()));
+            rest = &after[end..];
+        } else if let Some(after) = rest.strip_prefix('.') {
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']').ok_or_else(|| format!("Missing ']' in '{}'", path))?;
+            let selector = after[..end].trim();
+            steps.push(match selector {
+                "*" => Step::Wildcard,
+                _ => match selector.parse() {
+                    Ok(index) => Step::Index(index),
+                    Err(_) => Step::Key(selector.trim_matches(['\'', '"']).to_string()),
+                },
+            });
+            rest = &after[end + 1..];
+        } else {
+            let end = rest.find(['.', '[']).unwrap_or(rest.len());
+            let name = &rest[..end];
+            steps.push(if name == "*" { Step::Wildcard } else { Step::Key(name.to_string()) });
+            rest = &rest[end..];
+        }
+    }
+    Ok(steps)
+}
+
+// Prefers an exact key and otherwise matches case-insensitively, like the Query API does.
+fn get_key<'a>(value: &'a Value, key: &str) -> Option<&'a Value> {
+    match value {
+        Value::Object(map) => map.get(key).or_else(|| map.iter().find(|(name, _)| name.eq_ignore_ascii_case(key)).map(|(_, v)| v)),
+        // `Events.0` indexes an array like `Events[0]`.
+        Value::Array(_) => key.parse().ok().and_then(|index| get_index(value, index)),
+        _ => None,
+    }
+}
+
+// Negative indexes count from the end, so `[-1]` is the last element.
+fn get_index(value: &Value, index: i64) -> Option<&Value> {
+    let items = value.as_array()?;
+    let index = if index < 0 { items.len().checked_sub(index.unsigned_abs() as usize)? } else { index as usize };
+    items.get(index)
+}
+
+fn descend<'a>(value: &'a Value, key: &str, out: &mut Vec<&'a Value>) {
+    match value {
+        Value::Object(map) => {
+            out.extend(get_key(value, key));
+            map.values().for_each(|child| descend(child, key, out));
+        }
+        Value::Array(items) => items.iter().for_each(|item| descend(item, key, out)),
+        _ => {}
+    }
+}
+
+// Every value `path` selects in `value`. An invalid path selects nothing.
+pub fn select<'a>(value: &'a Value, path: &str) -> Vec<&'a Value> {
+    let Ok(steps) = parse_path(path) else {
+        return Vec::new();
+    };
+    steps.iter().fold(vec![value], |current, step| {
+        let mut next = Vec::new();
+        for value in current {
+            match step {
+                Step::Key(key) => next.extend(get_key(value, key)),
+                Step::Index(index) => next.extend(get_index(value, *index)),
+                Step::Wildcard => match value {
+                    Value::Array(items) => next.extend(items),
+                    Value::Object(map) => next.extend(map.values()),
+                    _ => {}
+                },
+                Step::Descend(key) => descend(value, key, &mut next),
+            }
+        }
+        next
+    })
+}
+
+// Paths with a wildcard or recursive descent can select several values per record.
+pub fn is_multi_valued(path: &str) -> bool {
+    path.contains('*') || path.contains("..")
+}
+
+// A plain field name, as opposed to a path the API can't sort or filter on.
+pub fn is_simple_field(path: &str) -> bool {
+    path.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// The value to print for `crop`: an array of every match for multi-valued paths.
+pub fn project(value: &Value, path: &str) -> Option<Value> {
+    let values = select(value, path);
+    if is_multi_valued(path) {
+        Some(Value::Array(values.into_iter().cloned().collect()))
+    } else {
+        values.first().map(|&value| value.clone())
+    }
+}
+
+pub fn validate_path(path: &str) -> Result<(), String> {
+    parse_path(path).map(|_| ())
+}
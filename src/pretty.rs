@@ -1,38 +1,96 @@
+use std::env;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicBool, Ordering};
 use serde_json::Value;
 use colored::*;
-use serde_yaml;
+use terminal_size::{terminal_size_of, Width};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+use crate::highlight::{highlight_json, highlight_yaml};
+
+// Results go to stdout and banners to stderr, so each stream decides on color separately.
+static STDOUT_COLOR: AtomicBool = AtomicBool::new(true);
+static STDERR_COLOR: AtomicBool = AtomicBool::new(true);
+static UNICODE: AtomicBool = AtomicBool::new(true);
+
+pub fn configure_terminal(args: &[String]) -> Result<(), String> {
+    let mode = args.iter().find_map(|arg| arg.strip_prefix("--color=")).unwrap_or("auto");
+    let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    let (stdout_color, stderr_color) = match mode {
+        "always" => (true, true),
+        "never" => (false, false),
+        "auto" => (!no_color && io::stdout().is_terminal(), !no_color && io::stderr().is_terminal()),
+        _ => return Err(format!("Invalid value '{}' for --color (use auto, always or never)", mode)),
+    };
+    let dumb_terminal = env::var("TERM").is_ok_and(|term| term == "dumb");
+    let unicode = !args.contains(&"--ascii".to_string()) && !dumb_terminal && io::stderr().is_terminal();
+
+    STDOUT_COLOR.store(stdout_color, Ordering::Relaxed);
+    STDERR_COLOR.store(stderr_color, Ordering::Relaxed);
+    UNICODE.store(unicode, Ordering::Relaxed);
+    colored::control::set_override(stdout_color || stderr_color);
+    Ok(())
+}
 
 pub fn prettify_json(json_str: &str) -> Result<String, String> {
     serde_json::from_str::<Value>(json_str)
         .map_err(|e| e.to_string())
         .and_then(|parsed| serde_json::to_string_pretty(&parsed).map_err(|e| e.to_string()))
-        .map(|formatted| colorize_output(&formatted))
+        .map(|formatted| colorize_output(&formatted, highlight_json))
 }
 
 pub fn prettify_yaml(json_str: &str) -> Result<String, String> {
     serde_json::from_str::<Value>(json_str)
         .map_err(|e| e.to_string())
         .and_then(|parsed| serde_yaml::to_string(&parsed).map_err(|e| e.to_string()))
-        .map(|formatted| colorize_output(&formatted))
+        .map(|formatted| colorize_output(&formatted, highlight_yaml))
 }
 
-fn colorize_output(text: &str) -> String {
-    text.lines()
-        .map(|line| {
-            let indent_level = line.chars().take_while(|c| c.is_whitespace()).count();
-            let depth = indent_level / 2; // Assuming 2 spaces per indentation level for YAML
-
-            let color = match depth {
-                0 => Color::White,
-                1 => Color::Green,
-                2 => Color::Blue,
-                _ => Color::Yellow,
-            };
+// Serializes each record straight to stdout, so no pretty-printed copy of the result is built.
+// A closed pipe (e.g. `| head`) ends the output quietly.
+pub fn write_ndjson(records: &[Value]) -> Result<(), String> {
+    let mut out = BufWriter::new(io::stdout().lock());
+    let result = records.iter().try_for_each(|record| {
+        serde_json::to_writer(&mut out, record)?;
+        out.write_all(b"\n")?;
+        Ok::<(), io::Error>(())
+    }).and_then(|_| out.flush());
 
-            line.color(color).to_string()
-        })
-        .collect::<Vec<_>>()
-        .join("\n")
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        other => other.map_err(|e| e.to_string()),
+    }
+}
+
+// Like `write_ndjson`, for records that arrive one at a time: `stream` is given a function that
+// writes a record and returns `Break` once stdout is closed or fails.
+pub fn write_ndjson_stream(stream: impl FnOnce(&mut dyn FnMut(Value) -> ControlFlow<()>) -> Result<(), String>) -> Result<(), String> {
+    let mut out = BufWriter::new(io::stdout().lock());
+    let mut error = None;
+    stream(&mut |record| {
+        let result = serde_json::to_writer(&mut out, &record).map_err(io::Error::from).and_then(|_| out.write_all(b"\n"));
+        match result {
+            Ok(()) => ControlFlow::Continue(()),
+            Err(e) => {
+                error = Some(e);
+                ControlFlow::Break(())
+            }
+        }
+    })?;
+
+    match error.map_or_else(|| out.flush(), Err) {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        other => other.map_err(|e| e.to_string()),
+    }
+}
+
+fn colorize_output(text: &str, highlight: fn(&str) -> String) -> String {
+    if STDOUT_COLOR.load(Ordering::Relaxed) {
+        highlight(text)
+    } else {
+        text.to_string()
+    }
 }
 
 pub fn fancy_status(status: &str) -> String {
@@ -48,26 +106,125 @@ pub fn fancy_status(status: &str) -> String {
     let trimmed_status = status.trim();
     let boxed_message = boxed_message(emoji, trimmed_status);
 
-    boxed_message.color(color).bold().to_string()
+    if STDERR_COLOR.load(Ordering::Relaxed) {
+        boxed_message.color(color).bold().to_string()
+    } else {
+        boxed_message
+    }
 }
 
-pub fn boxed_message(emoji: Option<&str>, message: &str) -> String {
-    let emoji_str = emoji.unwrap_or("");
-    let total_length = emoji_str.len() + message.len() + if emoji.is_some() { 2 } else { 0 }; // 2 for spaces around the emoji if present
-    let box_width = total_length - 1; 
+fn display_width(text: &str) -> usize {
+    text.graphemes(true).map(UnicodeWidthStr::width).sum()
+}
 
-    let top_border =    format!("┌─{:─<width$}┐", "", width = box_width);
-    let middle_line =   if emoji.is_some() {
-        format!("│ {} {:<width$} │", emoji_str, message, width = message.len() + emoji_str.len() - 3)
-    } else {
-        format!("│ {:<width$} │", message, width = message.len() - 3)
+fn terminal_width() -> usize {
+    terminal_size_of(io::stderr())
+        .map(|(Width(width), _)| width as usize)
+        .or_else(|| env::var("COLUMNS").ok().and_then(|columns| columns.parse().ok()))
+        .unwrap_or(80)
+}
+
+// Greedily fills lines up to `width` columns, breaking at spaces where possible and inside
+// words (e.g. URLs) only when a single word is wider than the line.
+fn wrap_line(line: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    let mut current_width = 0;
+
+    for word in line.split_word_bounds() {
+        let word_width = display_width(word);
+        if current_width + word_width > width && !current.trim().is_empty() {
+            lines.push(current.trim_end().to_string());
+            current.clear();
+            current_width = 0;
+            if word.trim().is_empty() {
+                continue;
+            }
+        }
+        for grapheme in word.graphemes(true) {
+            let grapheme_width = UnicodeWidthStr::width(grapheme);
+            if current_width + grapheme_width > width && !current.is_empty() {
+                lines.push(current.trim_end().to_string());
+                current.clear();
+                current_width = 0;
+            }
+            current.push_str(grapheme);
+            current_width += grapheme_width;
+        }
+    }
+    lines.push(current.trim_end().to_string());
+    lines
+}
+
+// Draws a box around the message. Lines are measured in terminal columns, so wide characters
+// and emoji line up, and lines longer than the terminal are wrapped. Each line of a
+// multi-line message gets its own row; continuation rows are indented past the emoji.
+pub fn boxed_message(emoji: Option<&str>, message: &str) -> String {
+    let unicode = UNICODE.load(Ordering::Relaxed);
+    let prefix = match emoji {
+        Some(emoji) if unicode => format!("{} ", emoji),
+        _ => String::new(),
     };
-    let bottom_border = format!("└─{:─<width$}┘", "", width = box_width);
-
-    format!(
-        "{}\n\
-         {}\n\
-         {}",
-        top_border, middle_line, bottom_border
-    )
+    let indent = " ".repeat(display_width(&prefix));
+    let content_width = terminal_width().saturating_sub(4 + indent.len()).max(10);
+
+    let rows: Vec<String> = message.lines()
+        .flat_map(|line| wrap_line(line, content_width))
+        .enumerate()
+        .map(|(i, line)| format!("{}{}", if i == 0 { &prefix } else { &indent }, line))
+        .collect();
+    let box_width = rows.iter().map(|row| display_width(row)).max().unwrap_or(0);
+
+    let (top, bottom, side, fill) = if unicode { (("┌", "┐"), ("└", "┘"), "│", "─") } else { (("+", "+"), ("+", "+"), "|", "-") };
+    let border = fill.repeat(box_width + 2);
+    let mut lines = vec![format!("{}{}{}", top.0, border, top.1)];
+    lines.extend(rows.iter().map(|row| format!("{} {}{} {}", side, row, " ".repeat(box_width - display_width(row)), side)));
+    lines.push(format!("{}{}{}", bottom.0, border, bottom.1));
+    lines.join("\n")
+}
+
+pub fn colorize_diff(text: &str) -> String {
+    if !STDOUT_COLOR.load(Ordering::Relaxed) {
+        return text.to_string();
+    }
+    text.lines()
+        .map(|line| {
+            let color = match line.chars().next() {
+                Some('+') => Color::Green,
+                Some('-') => Color::Red,
+                Some('~') => Color::Yellow,
+                _ => Color::Blue,
+            };
+
+            line.color(color).to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every row of a box is as wide as its borders, in terminal columns.
+    fn assert_aligned(boxed: &str) {
+        let widths: Vec<usize> = boxed.lines().map(display_width).collect();
+        assert!(widths.iter().all(|&width| width == widths[0]), "misaligned box:\n{}", boxed);
+    }
+
+    #[test]
+    fn boxes_emoji_and_wide_characters() {
+        assert_eq!(boxed_message(Some("⚠️"), "Hi"), "┌───────┐\n│ ⚠️ Hi │\n└───────┘");
+        assert_aligned(&boxed_message(Some("⚠️"), "Status: 300 Multiple Choices"));
+        assert_aligned(&boxed_message(Some("🔧"), "Config: ISV_Café"));
+        // The same name with a combining accent has one more character but the same width.
+        assert_aligned(&boxed_message(Some("🔧"), "Config: ISV_Cafe\u{301}"));
+        assert_aligned(&boxed_message(None, "設定: 東京"));
+    }
+
+    #[test]
+    fn boxes_a_one_character_message() {
+        assert_eq!(boxed_message(None, "x"), "┌───┐\n│ x │\n└───┘");
+        assert_eq!(boxed_message(Some("🚀"), "x"), "┌──────┐\n│ 🚀 x │\n└──────┘");
+    }
 }
@@ -0,0 +1,290 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Read;
+use std::ops::ControlFlow;
+use serde::de::{self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde_json::Value;
+use crate::args::get_nested_value;
+use crate::path::{is_simple_field, select};
+
+// Accepts either a raw API response (with a "Records" array) or a plain array of records.
+pub fn parse_records(text: &str) -> Result<Vec<Value>, String> {
+    match serde_json::from_str::<Value>(text).map_err(|e| e.to_string())? {
+        Value::Array(records) => Ok(records),
+        Value::Object(mut object) => match object.remove("Records") {
+            Some(Value::Array(records)) => Ok(records),
+            _ => Err("Invalid response format".to_string()),
+        },
+        _ => Err("Invalid response format".to_string()),
+    }
+}
+
+// Visits the elements of a records array, handing each one over as soon as it is parsed.
+struct RecordSeq<'a, F> {
+    each: &'a mut F,
+    stopped: &'a mut bool,
+}
+
+impl<'de, F: FnMut(Value) -> ControlFlow<()>> Visitor<'de> for RecordSeq<'_, F> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of records")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(record) = seq.next_element()? {
+            if (self.each)(record).is_break() {
+                // Stops reading the response; `stream_records` doesn't report this as an error.
+                *self.stopped = true;
+                return Err(de::Error::custom("stopped"));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'de, F: FnMut(Value) -> ControlFlow<()>> DeserializeSeed<'de> for RecordSeq<'_, F> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+// Visits a whole response: a plain array of records, or an object with a "Records" array.
+struct ResponseRecords<'a, F>(RecordSeq<'a, F>);
+
+impl<'de, F: FnMut(Value) -> ControlFlow<()>> Visitor<'de> for ResponseRecords<'_, F> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a response with a Records array")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<(), A::Error> {
+        self.0.visit_seq(seq)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let mut records = Some(self.0);
+        while let Some(key) = map.next_key::<String>()? {
+            match records.take_if(|_| key == "Records") {
+                Some(records) => map.next_value_seed(records)?,
+                None => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        match records {
+            Some(_) => Err(de::Error::custom("Invalid response format")),
+            None => Ok(()),
+        }
+    }
+}
+
+// Like `parse_records`, but reads the response from `reader` and passes each record to `each`
+// as it is parsed, so the records are never all in memory. `each` returns `Break` to stop early.
+pub fn stream_records(reader: impl Read, mut each: impl FnMut(Value) -> ControlFlow<()>) -> Result<(), String> {
+    let mut stopped = false;
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let visitor = ResponseRecords(RecordSeq { each: &mut each, stopped: &mut stopped });
+    match deserializer.deserialize_any(visitor).and_then(|_| deserializer.end()) {
+        Err(_) if stopped => Ok(()),
+        result => result.map_err(|e| e.to_string()),
+    }
+}
+
+// Flattens nested objects into dotted paths, e.g. `Customer.Lastname`.
+pub fn flatten(value: &Value, prefix: &str, out: &mut BTreeMap<String, Value>) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, child) in map {
+                let path = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                flatten(child, &path, out);
+            }
+        }
+        _ => {
+            out.insert(prefix.to_string(), value.clone());
+        }
+    }
+}
+
+fn value_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn compare_values(actual: &str, expected: &str) -> Ordering {
+    match (actual.parse::<f64>(), expected.parse::<f64>()) {
+        (Ok(a), Ok(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+        _ => actual.to_lowercase().cmp(&expected.to_lowercase()),
+    }
+}
+
+// Conditions the Query API has no operator for. Filters using them are applied to the fetched records.
+const LOCAL_CONDITIONS: [&str; 5] = ["isnull", "notnull", "exists", "nexists", "out"];
+
+// Local conditions, and fields given as paths such as `Events[*].TransEvent`, are evaluated on the fetched records.
+pub fn is_local_filter(key: &str) -> bool {
+    let field = key.split('(').next().unwrap_or_default();
+    LOCAL_CONDITIONS.iter().any(|condition| key.ends_with(&format!("({})", condition)))
+        || !field.split('.').all(is_simple_field)
+}
+
+fn matches_condition(actual: Option<&Value>, condition: &str, expected: &str) -> Result<bool, String> {
+    match condition {
+        "exists" => return Ok(actual.is_some()),
+        "nexists" => return Ok(actual.is_none()),
+        "isnull" => return Ok(actual.is_none_or(Value::is_null)),
+        "notnull" => return Ok(actual.is_some_and(|value| !value.is_null())),
+        _ => {}
+    }
+    let actual = actual.filter(|value| !value.is_null()).map(value_text);
+    let Some(actual) = actual else {
+        return Ok(matches!(condition, "ne" | "nct" | "nin" | "out"));
+    };
+    let in_list = || expected.split('|').any(|item| compare_values(&actual, item.trim()) == Ordering::Equal);
+    let matched = match condition {
+        "eq" => compare_values(&actual, expected) == Ordering::Equal,
+        "ne" => compare_values(&actual, expected) != Ordering::Equal,
+        "gt" => compare_values(&actual, expected) == Ordering::Greater,
+        "ge" => compare_values(&actual, expected) != Ordering::Less,
+        "lt" => compare_values(&actual, expected) == Ordering::Less,
+        "le" => compare_values(&actual, expected) != Ordering::Greater,
+        "ct" => actual.to_lowercase().contains(&expected.to_lowercase()),
+        "nct" => !actual.to_lowercase().contains(&expected.to_lowercase()),
+        "in" => in_list(),
+        "nin" => !in_list(),
+        "out" => {
+            let (start, end) = expected.split_once('|').ok_or_else(|| format!("Invalid range '{}'", expected))?;
+            compare_values(&actual, start) == Ordering::Less || compare_values(&actual, end) != Ordering::Less
+        }
+        _ => return Err(format!("Condition '{}' cannot be evaluated locally", condition)),
+    };
+    Ok(matched)
+}
+
+// A path that selects several values (e.g. `Events[*].TransEvent`) matches when any value does,
+// or for negative conditions such as `ne` and `nin`, when every value does.
+fn matches_field(record: &Value, field: &str, condition: &str, expected: &str) -> Result<bool, String> {
+    let values = select(record, field);
+    if values.is_empty() {
+        return matches_condition(None, condition, expected);
+    }
+    let negative = matches!(condition, "ne" | "nct" | "nin" | "out" | "isnull" | "nexists");
+    let mut results = values.into_iter().map(|value| matches_condition(Some(value), condition, expected));
+    if negative {
+        results.try_fold(true, |all, matched| Ok(all && matched?))
+    } else {
+        results.try_fold(false, |any, matched| Ok(any || matched?))
+    }
+}
+
+// Applies filters produced by `parse_filters` (e.g. `("status(eq)", "1")`) to records client-side.
+pub fn filter_records(records: Vec<Value>, filters: &[(String, String)]) -> Result<Vec<Value>, String> {
+    let conditions = filters.iter()
+        .map(|(key, expected)| {
+            let (field, condition) = key.strip_suffix(')')
+                .and_then(|key| key.split_once('('))
+                .ok_or_else(|| format!("Invalid filter '{}'", key))?;
+            Ok((field, condition, expected.as_str()))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let mut filtered = Vec::new();
+    for record in records {
+        let mut keep = true;
+        for (field, condition, expected) in &conditions {
+            if !matches_field(&record, field, condition, expected)? {
+                keep = false;
+                break;
+            }
+        }
+        if keep {
+            filtered.push(record);
+        }
+    }
+    Ok(filtered)
+}
+
+// Sorts numbers (and numeric strings such as "12.50") by value and everything else as text.
+// Records without the field come last in either order.
+pub fn sort_records(records: Vec<Value>, key: &str, order: &str) -> Vec<Value> {
+    let mut keyed: Vec<(Option<String>, Value)> = records.into_iter()
+        .map(|record| (get_nested_value(&record, key).filter(|value| !value.is_null()).map(value_text), record))
+        .collect();
+    keyed.sort_by(|(a, _), (b, _)| match (a, b) {
+        (Some(a), Some(b)) if order == "desc" => compare_values(b, a),
+        (Some(a), Some(b)) => compare_values(a, b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    });
+    keyed.into_iter().map(|(_, record)| record).collect()
+}
+
+// Applies the API's `sortBy`, `fromRecord` and `limitRecord` query parameters locally.
+pub fn apply_paging(records: Vec<Value>, params: &[(String, String)]) -> Vec<Value> {
+    let param = |name: &str| params.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str());
+    let records = match param("sortBy").and_then(|sort| sort.strip_suffix(')')?.split_once('(')) {
+        Some((order, field)) => sort_records(records, field, order),
+        None => records,
+    };
+    let skip = param("fromRecord").and_then(|value| value.parse().ok()).unwrap_or(0);
+    let limit = param("limitRecord").and_then(|value| value.parse().ok()).filter(|&limit| limit > 0).unwrap_or(usize::MAX);
+    records.into_iter().skip(skip).take(limit).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn stream(text: &str, limit: usize) -> Result<Vec<Value>, String> {
+        let mut records = Vec::new();
+        stream_records(text.as_bytes(), |record| {
+            records.push(record);
+            if records.len() == limit { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+        })?;
+        Ok(records)
+    }
+
+    fn amounts(records: &[Value]) -> Vec<i64> {
+        records.iter().map(|record| record["TotalAmount"].as_i64().unwrap()).collect()
+    }
+
+    #[test]
+    fn applies_paging_like_the_api() {
+        let records = || vec![json!({"TotalAmount": 3}), json!({"TotalAmount": 1}), json!({"TotalAmount": 2}), json!({"TotalAmount": 4})];
+        let params = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+            pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+        };
+        assert_eq!(amounts(&apply_paging(records(), &params(&[("sortBy", "desc(TotalAmount)"), ("fromRecord", "1"), ("limitRecord", "2")]))), vec![3, 2]);
+        assert_eq!(amounts(&apply_paging(records(), &params(&[("sortBy", "asc(TotalAmount)"), ("limitRecord", "0")]))), vec![1, 2, 3, 4]);
+        assert_eq!(amounts(&apply_paging(records(), &params(&[("fromRecord", "3")]))), vec![4]);
+    }
+
+    #[test]
+    fn streams_records_of_a_response_or_an_array() {
+        let response = r#"{"Summary": {"totalRecords": 2}, "Records": [{"IdTrans": 1}, {"IdTrans": 2}], "After": null}"#;
+        assert_eq!(stream(response, 0).unwrap(), vec![json!({"IdTrans": 1}), json!({"IdTrans": 2})]);
+        assert_eq!(stream(r#"[{"IdTrans": 1}]"#, 0).unwrap(), vec![json!({"IdTrans": 1})]);
+    }
+
+    #[test]
+    fn stops_streaming_on_break() {
+        // The rest of the response is not read, so it isn't reported even when invalid.
+        assert_eq!(stream(r#"{"Records": [{"IdTrans": 1}, {"IdTrans": 2}, oops"#, 1).unwrap(), vec![json!({"IdTrans": 1})]);
+    }
+
+    #[test]
+    fn rejects_responses_without_records() {
+        assert!(stream(r#"{"Summary": {}}"#, 0).is_err());
+        assert!(stream(r#"{"Records": [{"IdTrans": 1}]"#, 0).is_err());
+        assert!(stream("42", 0).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use std::sync::OnceLock;
+use regex::Regex;
+use serde_json::Value;
+use crate::config::EnvironmentConfig;
+
+// Words of field names that hold cardholder, customer or bank data in Query API records.
+const PII_TERMS: &[&str] = &[
+    "firstname", "lastname", "holdername", "customername", "billingname", "contactname", "email", "phone",
+    "address", "zip", "zipcode", "postalcode", "maskedaccount", "accountnumber", "accountexp", "routing",
+    "bankaccount", "bankname", "ssn", "taxid", "dob", "dateofbirth", "cardnumber", "ipaddress",
+];
+
+// A term matches whole CamelCase words of the last path segment (`BillingEmail`, `Address1`,
+// `contact_phone`), so `ssn` doesn't match inside `BusinessName`.
+fn preset_rule() -> Regex {
+    let terms: Vec<String> = PII_TERMS.iter()
+        .map(|term| {
+            let (first, rest) = term.split_at(1);
+            format!(r"(?:^|\.)(?:[^.]*_)?(?i:{})|(?:^|\.)[^.]*[a-z\d]{}(?i:{})", term, first.to_uppercase(), rest)
+        })
+        .collect();
+    Regex::new(&format!(r"(?:{})(?:[A-Z\d_][^.]*)?$", terms.join("|"))).unwrap()
+}
+
+pub struct Redactor {
+    rules: Vec<Regex>,
+}
+
+static REDACTOR: OnceLock<Redactor> = OnceLock::new();
+
+// A rule is either a dotted field path (`Customer.Email`, matched case-insensitively)
+// or a regular expression between slashes (`/(?i)notes$/`) matched against the dotted path.
+fn parse_rule(rule: &str) -> Result<Regex, String> {
+    let pattern = match rule.strip_prefix('/').and_then(|rule| rule.strip_suffix('/')) {
+        Some(pattern) => pattern.to_string(),
+        None => format!("(?i)^{}$", regex::escape(rule)),
+    };
+    Regex::new(&pattern).map_err(|e| format!("Invalid redaction rule '{}': {}", rule, e))
+}
+
+// Enables redaction when `--redact` is given or the configuration sets `redact: true`.
+pub fn configure_redaction(args: &[String], env_config: Option<&EnvironmentConfig>) -> Result<(), String> {
+    let enabled = args.contains(&"--redact".to_string()) || env_config.is_some_and(|config| config.redact);
+    if !enabled {
+        return Ok(());
+    }
+    let mut rules = vec![preset_rule()];
+    for rule in env_config.map_or(&[][..], |config| &config.redact_fields) {
+        rules.push(parse_rule(rule)?);
+    }
+    let _ = REDACTOR.set(Redactor { rules });
+    Ok(())
+}
+
+fn mask(value: &Value) -> Value {
+    match value {
+        Value::Null => Value::Null,
+        Value::String(s) if s.is_empty() => Value::String(String::new()),
+        // Keep the last four digits of account-like values so records can still be told apart.
+        Value::String(s) if s.len() >= 8 && s.chars().all(|c| c.is_ascii_digit() || c == 'X' || c == '*' || c == '-') => {
+            Value::String(format!("****{}", &s[s.len() - 4..]))
+        }
+        _ => Value::String("****".to_string()),
+    }
+}
+
+impl Redactor {
+    fn matches(&self, path: &str) -> bool {
+        !path.is_empty() && self.rules.iter().any(|rule| rule.is_match(path))
+    }
+
+    fn redact(&self, value: &mut Value, path: &str) {
+        if self.matches(path) {
+            match value {
+                Value::Object(map) => map.values_mut().for_each(|child| *child = mask(child)),
+                Value::Array(items) => items.iter_mut().for_each(|item| *item = mask(item)),
+                other => *other = mask(other),
+            }
+            return;
+        }
+        match value {
+            Value::Object(map) => {
+                for (key, child) in map.iter_mut() {
+                    let child_path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                    self.redact(child, &child_path);
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(|item| self.redact(item, path)),
+            _ => {}
+        }
+    }
+}
+
+pub fn redact_records(mut records: Vec<Value>) -> Vec<Value> {
+    if let Some(redactor) = REDACTOR.get() {
+        records.iter_mut().for_each(|record| redactor.redact(record, ""));
+    }
+    records
+}
+
+pub fn is_redacting() -> bool {
+    REDACTOR.get().is_some()
+}
+
+// Masks `value` as it would be masked at `path` in a record, including when a parent of
+// `path` is redacted. An empty path redacts a whole record.
+pub fn redact_field(path: &str, mut value: Value) -> Value {
+    if let Some(redactor) = REDACTOR.get() {
+        if path.match_indices('.').any(|(i, _)| redactor.matches(&path[..i])) {
+            return mask(&value);
+        }
+        redactor.redact(&mut value, path);
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preset_matches_whole_words() {
+        let rule = preset_rule();
+        for path in ["Email", "Customer.Email", "BillingEmail", "FirstName", "Customer.Firstname", "BillingAddress1", "contact_phone", "PaymentData.MaskedAccount", "IPAddress", "EmailAddress"] {
+            assert!(rule.is_match(path), "{} should be masked", path);
+        }
+        for path in ["BusinessName", "Customer.Emails", "Zipped", "Address.Type", "Dobson"] {
+            assert!(!rule.is_match(path), "{} should not be masked", path);
+        }
+    }
+}
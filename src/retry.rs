@@ -0,0 +1,132 @@
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use rand::{thread_rng, Rng};
+use reqwest::blocking::Response;
+use reqwest::header::RETRY_AFTER;
+use reqwest::StatusCode;
+use crate::config::RetryConfig;
+
+const DEFAULT_RETRIES: u32 = 3;
+const DEFAULT_BASE_DELAY_MS: u64 = 500;
+const DEFAULT_MAX_DELAY_MS: u64 = 30_000;
+
+pub struct RetryPolicy {
+    retries: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    // The shortest time between two requests, from the rate limit.
+    interval: Option<Duration>,
+}
+
+static POLICY: OnceLock<RetryPolicy> = OnceLock::new();
+static LAST_REQUEST: Mutex<Option<Instant>> = Mutex::new(None);
+
+// `--retries N` and `--rate-limit N` take precedence over the `retry` section of payquery.yml.
+pub fn configure_retries(retries: Option<String>, rate_limit: Option<String>, config: Option<&RetryConfig>) -> Result<(), String> {
+    let config = config.cloned().unwrap_or_default();
+    let retries = match retries {
+        Some(value) => value.parse().map_err(|_| format!("Invalid --retries '{}' (use a number, 0 to disable retries)", value))?,
+        None => config.retries.unwrap_or(DEFAULT_RETRIES),
+    };
+    let rate_limit = match rate_limit {
+        Some(value) => Some(value.parse().map_err(|_| format!("Invalid --rate-limit '{}' (use a number of requests per second)", value))?),
+        None => config.rate_limit,
+    };
+    let interval = match rate_limit {
+        Some(rate) if rate > 0.0 && rate.is_finite() => Some(Duration::from_secs_f64(1.0 / rate)),
+        Some(rate) => return Err(format!("Invalid rate limit '{}' (use a positive number of requests per second)", rate)),
+        None => None,
+    };
+    let _ = POLICY.set(RetryPolicy {
+        retries,
+        base_delay: Duration::from_millis(config.base_delay_ms.unwrap_or(DEFAULT_BASE_DELAY_MS)),
+        max_delay: Duration::from_millis(config.max_delay_ms.unwrap_or(DEFAULT_MAX_DELAY_MS)),
+        interval,
+    });
+    Ok(())
+}
+
+fn policy() -> &'static RetryPolicy {
+    POLICY.get_or_init(|| RetryPolicy {
+        retries: DEFAULT_RETRIES,
+        base_delay: Duration::from_millis(DEFAULT_BASE_DELAY_MS),
+        max_delay: Duration::from_millis(DEFAULT_MAX_DELAY_MS),
+        interval: None,
+    })
+}
+
+impl RetryPolicy {
+    // Doubles with every retry up to `max_delay`; half of it is random so that several
+    // clients failing together don't retry in step.
+    fn backoff(&self, retry: u32) -> Duration {
+        let delay = self.base_delay.saturating_mul(2u32.saturating_pow(retry)).min(self.max_delay);
+        delay / 2 + delay.mul_f64(thread_rng().gen_range(0.0..0.5))
+    }
+}
+
+// Holds a request back until the rate limit allows it.
+fn wait_for_turn(interval: Option<Duration>) {
+    let mut last_request = LAST_REQUEST.lock().unwrap();
+    if let (Some(interval), Some(last)) = (interval, *last_request) {
+        thread::sleep(interval.saturating_sub(last.elapsed()));
+    }
+    *last_request = Some(Instant::now());
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::REQUEST_TIMEOUT || status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+// `Retry-After` is either a number of seconds or an HTTP date. It is capped at `max_delay`, so a
+// server asking for hours doesn't hold the command that long.
+fn retry_after(response: &Response, max_delay: Duration) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    let delay = match value.parse() {
+        Ok(seconds) => Duration::from_secs(seconds),
+        Err(_) => {
+            let date = DateTime::parse_from_rfc2822(value).ok()?;
+            (date.with_timezone(&Utc) - Utc::now()).to_std().unwrap_or_default()
+        }
+    };
+    Some(delay.min(max_delay))
+}
+
+// Sends a request, retrying connection errors, timeouts, 429s and 5xx responses, and hands the
+// response to `read` (e.g. `Response::text`). When every attempt fails, the error lists what
+// happened to each one.
+pub fn send_with_retries<T>(send: impl Fn() -> reqwest::Result<Response>, read: impl Fn(Response) -> reqwest::Result<T>) -> Result<(StatusCode, T), String> {
+    let policy = policy();
+    let mut failures: Vec<String> = Vec::new();
+
+    for retry in 0.. {
+        wait_for_turn(policy.interval);
+        let result = send().and_then(|response| {
+            let status = response.status();
+            let retry_after = retry_after(&response, policy.max_delay);
+            read(response).map(|body| (status, body, retry_after))
+        });
+        let retry_after = match result {
+            Ok((status, body, _)) if !is_retryable(status) || policy.retries == 0 => return Ok((status, body)),
+            Ok((status, _, retry_after)) => {
+                failures.push(status.to_string());
+                retry_after
+            }
+            Err(e) if e.is_builder() || policy.retries == 0 => return Err(format!("Request failed: {}", e)),
+            Err(e) => {
+                failures.push(e.to_string());
+                None
+            }
+        };
+        if retry == policy.retries {
+            break;
+        }
+        let delay = retry_after.unwrap_or_else(|| policy.backoff(retry));
+        eprintln!("Warning: {}, retrying in {:.1}s (retry {} of {})", failures[failures.len() - 1], delay.as_secs_f64(), retry + 1, policy.retries);
+        thread::sleep(delay);
+    }
+
+    let attempts: Vec<String> = failures.iter().enumerate().map(|(i, failure)| format!("  {}. {}", i + 1, failure)).collect();
+    Err(format!("Request failed after {} attempts:\n{}", failures.len(), attempts.join("\n")))
+}
@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::io::{ErrorKind, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+use chrono::Local;
+use serde_json::Value;
+use crate::args::{record_key, subcommand_position};
+use crate::redact::redact_records;
+
+pub struct WatchOptions {
+    pub interval: Duration,
+    pub bell: bool,
+    pub hook: Option<String>,
+}
+
+// `watch` is a subcommand, so it is only recognized before the endpoint and a filter value
+// such as `Description ct watch` is left alone.
+pub fn extract_watch_clause(args: &[String]) -> Result<(Vec<String>, Option<WatchOptions>), String> {
+    let Some(pos) = subcommand_position(args).filter(|&pos| args[pos] == "watch") else {
+        return Ok((args.to_vec(), None));
+    };
+    let interval_arg = args.get(pos + 1).ok_or("Missing interval after 'watch' (e.g. 'watch 30s')")?;
+    let mut options = WatchOptions {
+        interval: parse_interval(interval_arg)?,
+        bell: false,
+        hook: None,
+    };
+
+    let mut remaining = Vec::new();
+    let mut rest = args[..pos].iter().chain(&args[pos + 2..]);
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--bell" => options.bell = true,
+            "--hook" => options.hook = Some(rest.next().ok_or("Missing command after '--hook'")?.clone()),
+            _ => remaining.push(arg.clone()),
+        }
+    }
+
+    Ok((remaining, Some(options)))
+}
+
+pub fn parse_interval(value: &str) -> Result<Duration, String> {
+    let split_at = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split_at);
+    let number: u64 = number.parse().map_err(|_| format!("Invalid interval '{}'", value))?;
+    let seconds = match unit {
+        "" | "s" => number,
+        "m" => number * 60,
+        "h" => number * 3600,
+        _ => return Err(format!("Invalid interval unit '{}' (use s, m or h)", unit)),
+    };
+    if seconds == 0 {
+        return Err("Interval must be greater than zero".to_string());
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+// Returns the records that were not seen before or whose content changed since the last poll.
+fn detect_changes(seen: &mut HashMap<String, String>, records: &[Value], key: Option<&str>) -> Vec<Value> {
+    records
+        .iter()
+        .filter(|record| {
+            let content = record.to_string();
+            seen.insert(record_key(record, key), content.clone()).is_none_or(|previous| previous != content)
+        })
+        .cloned()
+        .collect()
+}
+
+fn run_hook(command: &str, records: &[Value]) -> Result<(), String> {
+    let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+    let mut child = Command::new(shell)
+        .args([flag, command])
+        .env("PAYQUERY_NEW_RECORDS", records.len().to_string())
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+
+    // The records are written from another thread while the hook is waited for, so a hook that
+    // exits without reading its stdin neither stalls the write nor is left unwaited. The pipe it
+    // closes is not an error.
+    let payload = serde_json::to_string(records).map_err(|e| e.to_string());
+    let writer = child.stdin.take().map(|mut stdin| {
+        thread::spawn(move || match payload {
+            Ok(payload) => match stdin.write_all(payload.as_bytes()) {
+                Err(e) if e.kind() != ErrorKind::BrokenPipe => Err(e.to_string()),
+                _ => Ok(()),
+            },
+            Err(e) => Err(e),
+        })
+    });
+    let status = child.wait().map_err(|e| e.to_string());
+    if let Some(writer) = writer {
+        writer.join().map_err(|_| "Failed to write the records to the hook".to_string())??;
+    }
+    status.map(|_| ())
+}
+
+pub fn run<F, O>(options: &WatchOptions, key: Option<&str>, mut fetch: F, mut output: O) -> !
+where
+    F: FnMut() -> Result<Vec<Value>, String>,
+    O: FnMut(Vec<Value>),
+{
+    let mut seen = HashMap::new();
+    let mut first_poll = true;
+
+    loop {
+        match fetch() {
+            Ok(records) => {
+                let changes = detect_changes(&mut seen, &records, key);
+                if first_poll {
+                    eprintln!("Watching {} records, polling every {}s. Press Ctrl+C to stop.", records.len(), options.interval.as_secs());
+                    first_poll = false;
+                } else if !changes.is_empty() {
+                    eprintln!("[{}] {} new or changed records", Local::now().format("%H:%M:%S"), changes.len());
+                    if options.bell {
+                        eprint!("\x07");
+                    }
+                    if let Some(hook) = &options.hook
+                        && let Err(e) = run_hook(hook, &redact_records(changes.clone())) {
+                        eprintln!("Hook failed: {}", e);
+                    }
+                    output(changes);
+                }
+            }
+            Err(e) => eprintln!("[{}] {}", Local::now().format("%H:%M:%S"), e),
+        }
+        thread::sleep(options.interval);
+    }
+}
@@ -0,0 +1,111 @@
+use rand::seq::index;
+use rand::thread_rng;
+use serde_json::Value;
+use crate::args::quoted_words;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum WindowMode {
+    First,
+    Last,
+    Sample,
+}
+
+// Which records to keep after sorting: `skip` records are dropped first, then `count`
+// records are taken from the start, from the end or at random.
+#[derive(Clone)]
+pub struct Window {
+    pub skip: usize,
+    pub count: Option<usize>,
+    pub mode: WindowMode,
+}
+
+impl Default for Window {
+    fn default() -> Self {
+        Window { skip: 0, count: None, mode: WindowMode::First }
+    }
+}
+
+fn parse_count(args: &[String], pos: usize) -> Option<usize> {
+    args.get(pos).and_then(|value| value.parse().ok())
+}
+
+// Removes `only N`, `first N`, `last N`, `sample N`, `skip N` and `page P of size S` from the
+// arguments. A keyword that is not followed by a number (e.g. `last week`) is left alone.
+pub fn extract_window_clause(args: &[String]) -> Result<(Vec<String>, Window), String> {
+    let quoted = quoted_words(args);
+    let mut window = Window::default();
+    let mut remaining = Vec::new();
+    let mut i = 0;
+
+    while i < args.len() {
+        let keyword = if quoted[i] { "" } else { args[i].as_str() };
+        let (count, mode, consumed) = match (keyword, parse_count(args, i + 1)) {
+            ("only" | "first", Some(count)) => (count, WindowMode::First, 2),
+            ("last", Some(count)) => (count, WindowMode::Last, 2),
+            ("sample", Some(count)) => (count, WindowMode::Sample, 2),
+            ("skip", Some(skip)) => {
+                window.skip += skip;
+                i += 2;
+                continue;
+            }
+            ("page", Some(page)) => {
+                let size = match (args.get(i + 2), args.get(i + 3)) {
+                    (Some(of), Some(size)) if of == "of" && size == "size" => parse_count(args, i + 4),
+                    _ => None,
+                };
+                let size = size.ok_or("Invalid page clause (use 'page P of size S')")?;
+                if page == 0 {
+                    return Err("Pages are numbered from 1".to_string());
+                }
+                if size == 0 {
+                    return Err("Invalid page size 0 (use a size of at least 1)".to_string());
+                }
+                window.skip += (page - 1) * size;
+                (size, WindowMode::First, 5)
+            }
+            _ => {
+                remaining.push(args[i].clone());
+                i += 1;
+                continue;
+            }
+        };
+        if count == 0 {
+            return Err(format!("Invalid '{} 0' (use a count of at least 1)", keyword));
+        }
+        if window.count.is_some() {
+            return Err("Only one of only, first, last, sample or page can be used".to_string());
+        }
+        window.count = Some(count);
+        window.mode = mode;
+        i += consumed;
+    }
+
+    Ok((remaining, window))
+}
+
+impl Window {
+    pub fn apply(&self, records: Vec<Value>) -> Vec<Value> {
+        let mut records: Vec<Value> = records.into_iter().skip(self.skip).collect();
+        let Some(count) = self.count else {
+            return records;
+        };
+        match self.mode {
+            WindowMode::First => {
+                records.truncate(count);
+                records
+            }
+            WindowMode::Last => records.split_off(records.len().saturating_sub(count)),
+            // Keeps the sampled records in their original (sorted) order.
+            WindowMode::Sample => {
+                let mut picked = index::sample(&mut thread_rng(), records.len(), count.min(records.len())).into_vec();
+                picked.sort_unstable();
+                let mut picked = picked.into_iter().peekable();
+                records.into_iter()
+                    .enumerate()
+                    .filter(|(i, _)| picked.next_if_eq(i).is_some())
+                    .map(|(_, record)| record)
+                    .collect()
+            }
+        }
+    }
+}